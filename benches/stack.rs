@@ -0,0 +1,135 @@
+//! Compares the machine's `LinkedList` stack with a plain `Vec` on the kind
+//! of deep recursion the teleporter confirmation routine does, where every
+//! call and return goes through the stack.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use std::collections::LinkedList;
+use synacor_challenge::asm::assemble;
+use synacor_challenge::Machine;
+
+const MODULUS: u16 = 32768;
+
+trait Stack: Default {
+    fn push(&mut self, value: u16);
+    fn pop(&mut self) -> Option<u16>;
+}
+
+impl Stack for LinkedList<u16> {
+    fn push(&mut self, value: u16) {
+        self.push_front(value);
+    }
+
+    fn pop(&mut self) -> Option<u16> {
+        self.pop_front()
+    }
+}
+
+impl Stack for Vec<u16> {
+    fn push(&mut self, value: u16) {
+        Vec::push(self, value);
+    }
+
+    fn pop(&mut self) -> Option<u16> {
+        Vec::pop(self)
+    }
+}
+
+/// Return addresses pushed by the two kinds of call site in the routine.
+const RETURN_THEN_RET: u16 = 6046;
+const RETURN_TO_SECOND_CALL: u16 = 6056;
+
+enum At {
+    Entry,
+    NonZeroR0,
+    NonZeroR1,
+    SecondCall,
+    Ret,
+}
+
+/// The confirmation routine at 6027, with calls and returns going through
+/// `S` exactly as the machine would execute them.
+fn confirm<S: Stack>(mut r0: u16, mut r1: u16, r7: u16) -> u16 {
+    let mut stack = S::default();
+    let mut at = At::Entry;
+    loop {
+        at = match at {
+            At::Entry if r0 != 0 => At::NonZeroR0,
+            At::Entry => {
+                r0 = (r1 + 1) % MODULUS;
+                At::Ret
+            }
+            At::NonZeroR0 if r1 != 0 => At::NonZeroR1,
+            At::NonZeroR0 => {
+                r0 = (r0 + MODULUS - 1) % MODULUS;
+                r1 = r7;
+                stack.push(RETURN_THEN_RET);
+                At::Entry
+            }
+            At::NonZeroR1 => {
+                stack.push(r0);
+                r1 = (r1 + MODULUS - 1) % MODULUS;
+                stack.push(RETURN_TO_SECOND_CALL);
+                At::Entry
+            }
+            At::SecondCall => {
+                r1 = r0;
+                r0 = (stack.pop().unwrap() + MODULUS - 1) % MODULUS;
+                stack.push(RETURN_THEN_RET);
+                At::Entry
+            }
+            At::Ret => match stack.pop() {
+                None => return r0,
+                Some(RETURN_TO_SECOND_CALL) => At::SecondCall,
+                Some(_) => At::Ret,
+            },
+        }
+    }
+}
+
+const ROUTINE: &str = "
+    set r0 3
+    set r1 1
+    set r7 1
+    call confirm
+    halt
+confirm:
+    jt r0 +8
+    add r0 r1 1
+    ret
+    jt r1 +13
+    add r0 r0 32767
+    set r1 r7
+    call confirm
+    ret
+    push r0
+    add r1 r1 32767
+    call confirm
+    set r1 r0
+    pop r0
+    add r0 r0 32767
+    call confirm
+    ret
+";
+
+fn stacks(c: &mut Criterion) {
+    let mut group = c.benchmark_group("confirm(3, 1, r7 = 1)");
+    group.bench_function("LinkedList", |b| {
+        b.iter(|| confirm::<LinkedList<u16>>(black_box(3), black_box(1), black_box(1)))
+    });
+    group.bench_function("Vec", |b| {
+        b.iter(|| confirm::<Vec<u16>>(black_box(3), black_box(1), black_box(1)))
+    });
+    let program = assemble(ROUTINE).unwrap();
+    let run = || {
+        let mut machine = Machine::new(program.clone());
+        machine.run().unwrap();
+        machine.registers[0]
+    };
+    assert_eq!(run(), confirm::<Vec<u16>>(3, 1, 1));
+    assert_eq!(run(), confirm::<LinkedList<u16>>(3, 1, 1));
+    group.bench_function("Machine", |b| b.iter(run));
+    group.finish();
+}
+
+criterion_group!(benches, stacks);
+criterion_main!(benches);
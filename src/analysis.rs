@@ -0,0 +1,705 @@
+use crate::symbols::Symbols;
+use crate::{decode_at, get_addr, Address, Instruction, Oprnd, INTEGER_RANGE, NUMBER_OF_REGISTERS};
+use std::collections::{BTreeMap, BTreeSet};
+
+pub const MIN_STRING_LEN: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum StringKind {
+    /// Printable words stored as data.
+    Data,
+    /// A run of consecutive `out` instructions with literal operands.
+    Out,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct StringBlock {
+    pub kind: StringKind,
+    pub start: u16,
+    pub end: u16,
+    pub text: String,
+    pub refs: Vec<u16>,
+}
+
+impl StringBlock {
+    pub fn word_count(&self) -> usize {
+        (self.end - self.start) as usize + 1
+    }
+
+    fn contains(&self, addr: u16) -> bool {
+        self.start <= addr && addr <= self.end
+    }
+}
+
+fn literal(oprnd: u16) -> Option<u16> {
+    if (oprnd as usize) < INTEGER_RANGE {
+        Some(oprnd)
+    } else {
+        None
+    }
+}
+
+fn printable(word: u16) -> Option<char> {
+    match word {
+        10 | 32..=126 => Some(word as u8 as char),
+        _ => None,
+    }
+}
+
+/// Where execution can continue after an instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Successor {
+    /// The instruction following it in memory.
+    FallThrough(u16),
+    /// The target of `jmp`, `jt` or `jf`.
+    Jump(u16),
+    /// The subroutine a `call` enters.
+    Call(u16),
+    /// Where the callee of a `call` comes back to.
+    Return(u16),
+    /// A target only known at run time: a register operand, or the address
+    /// `ret` pops.
+    Dynamic,
+}
+
+impl Successor {
+    pub fn address(self) -> Option<u16> {
+        match self {
+            Successor::FallThrough(addr)
+            | Successor::Jump(addr)
+            | Successor::Call(addr)
+            | Successor::Return(addr) => Some(addr),
+            Successor::Dynamic => None,
+        }
+    }
+}
+
+/// Every way execution can continue after `instr` at `addr`, worked out
+/// from the instruction alone. `halt` has none.
+pub fn next_addresses(addr: u16, instr: Instruction) -> Vec<Successor> {
+    let next = addr.wrapping_add(instr.size());
+    let target = |oprnd: u16, known: fn(u16) -> Successor| match literal(oprnd) {
+        Some(target) => known(target),
+        None => Successor::Dynamic,
+    };
+    match instr {
+        Instruction::Halt => vec![],
+        Instruction::Ret => vec![Successor::Dynamic],
+        Instruction::Jmp(a) => vec![target(a, Successor::Jump)],
+        Instruction::Jt(_, b) | Instruction::Jf(_, b) => {
+            vec![target(b, Successor::Jump), Successor::FallThrough(next)]
+        }
+        Instruction::Call(a) => vec![target(a, Successor::Call), Successor::Return(next)],
+        _ => vec![Successor::FallThrough(next)],
+    }
+}
+
+/// Statically known addresses execution can continue at after `instr`.
+fn successors(addr: u16, instr: Instruction) -> Vec<u16> {
+    next_addresses(addr, instr)
+        .into_iter()
+        .filter_map(Successor::address)
+        .collect()
+}
+
+/// Follows the control flow from address 0 without executing anything and
+/// returns every instruction it can reach. Jumps and calls through registers
+/// cannot be followed statically and are ignored.
+pub fn reachable_code(memory: &[u16]) -> BTreeMap<u16, Instruction> {
+    let mut code: BTreeMap<u16, Instruction> = BTreeMap::new();
+    let mut pending: Vec<u16> = vec![0];
+
+    while let Some(addr) = pending.pop() {
+        if code.contains_key(&addr) {
+            continue;
+        }
+        let instr = match decode_at(memory, addr as usize) {
+            Some(instr) => instr,
+            None => continue,
+        };
+        code.insert(addr, instr);
+        pending.extend(successors(addr, instr));
+    }
+
+    code
+}
+
+/// Decodes linearly from address 0, without following control flow, and
+/// returns the address of the first word that does not start a valid
+/// instruction, or the number of instructions decoded if all of memory
+/// does. Data mixed into the code shows up as such a word, so this only
+/// suits programs that are pure code.
+pub fn linear_decode(memory: &[u16]) -> Result<usize, usize> {
+    let mut decoded = 0;
+    let mut addr = 0;
+    while addr < memory.len() {
+        match decode_at(memory, addr) {
+            Some(instr) => addr += instr.size() as usize,
+            None => return Err(addr),
+        }
+        decoded += 1;
+    }
+    Ok(decoded)
+}
+
+/// How the cells of memory are used, from `memory_map`. The four counts
+/// add up to the size of memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MemoryMap {
+    /// Cells of statically reachable instructions, operands included.
+    pub code: usize,
+    /// Cells of data strings that are not code.
+    pub data: usize,
+    /// Zero cells that are neither.
+    pub unused: usize,
+    /// Everything else: unreachable code, numeric tables and the like.
+    pub other: usize,
+}
+
+/// Classifies every cell using `reachable_code` and `string_map`.
+pub fn memory_map(memory: &[u16]) -> MemoryMap {
+    let mut code = vec![false; memory.len()];
+    for (&addr, instr) in &reachable_code(memory) {
+        let start = addr as usize;
+        let end = (start + instr.size() as usize).min(memory.len());
+        code[start..end].iter_mut().for_each(|cell| *cell = true);
+    }
+    let mut data = vec![false; memory.len()];
+    for block in string_map(memory, MIN_STRING_LEN) {
+        if block.kind == StringKind::Data {
+            data[block.start as usize..=block.end as usize]
+                .iter_mut()
+                .for_each(|cell| *cell = true);
+        }
+    }
+    let mut map = MemoryMap {
+        code: 0,
+        data: 0,
+        unused: 0,
+        other: 0,
+    };
+    for (addr, &word) in memory.iter().enumerate() {
+        if code[addr] {
+            map.code += 1;
+        } else if data[addr] {
+            map.data += 1;
+        } else if word == 0 {
+            map.unused += 1;
+        } else {
+            map.other += 1;
+        }
+    }
+    map
+}
+
+/// The lowest address of an `in` in the `reachable_code`, where the
+/// program first turns interactive; before it is the self-test. `None` if
+/// no input is read along the statically known control flow.
+pub fn find_first_input(memory: &[u16]) -> Option<u16> {
+    reachable_code(memory)
+        .into_iter()
+        .find(|(_, instr)| matches!(instr, Instruction::In(_)))
+        .map(|(addr, _)| addr)
+}
+
+/// Result of `verify_bytecode`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Verification {
+    /// Structural problems, as the address of the offending instruction and
+    /// a description.
+    pub problems: Vec<(u16, String)>,
+    /// Jumps, calls and returns whose targets depend on runtime values, so
+    /// the code behind them was not checked.
+    pub unresolved: BTreeSet<u16>,
+}
+
+/// Checks the statically reachable code from address 0: every operand must
+/// be a number or a register, destinations must be registers and control
+/// flow must land on words that decode. Code only reached through registers,
+/// returns or self-modification is not verified; the instructions where the
+/// walk lost track are listed in `unresolved`.
+pub fn verify_bytecode(memory: &[u16]) -> Verification {
+    let code = reachable_code(memory);
+    let mut problems = Vec::new();
+    let mut unresolved = BTreeSet::new();
+
+    for (&addr, &instr) in &code {
+        if let Some(dest) = instr.destination() {
+            if let Some(Address::Mem(_)) | None = get_addr(dest, NUMBER_OF_REGISTERS) {
+                problems.push((
+                    addr,
+                    format!("destination {} is not a register", Oprnd(dest)),
+                ));
+            }
+        }
+        for oprnd in instr.sources() {
+            if get_addr(oprnd, NUMBER_OF_REGISTERS).is_none() {
+                problems.push((addr, format!("operand {} is out of range", oprnd)));
+            }
+        }
+        match instr {
+            Instruction::Ret => {
+                unresolved.insert(addr);
+            }
+            Instruction::Jmp(a) | Instruction::Call(a) if literal(a).is_none() => {
+                unresolved.insert(addr);
+            }
+            Instruction::Jt(_, b) | Instruction::Jf(_, b) if literal(b).is_none() => {
+                unresolved.insert(addr);
+            }
+            _ => {}
+        }
+        for target in successors(addr, instr) {
+            if !code.contains_key(&target) {
+                let word = memory.get(target as usize);
+                problems.push((
+                    addr,
+                    match word {
+                        Some(word) => format!(
+                            "continues at {}, which holds invalid opcode {}",
+                            target, word
+                        ),
+                        None => format!("continues at {}, outside memory", target),
+                    },
+                ));
+            }
+        }
+    }
+
+    Verification {
+        problems,
+        unresolved,
+    }
+}
+
+/// Literal jump and call targets in the reachable code that land inside
+/// another reachable instruction rather than at its start, as
+/// `(source, target, containing instruction)`. Such a target decodes a
+/// different instruction stream than the one around it, which in
+/// hand-written code is almost always a mistake. Self-modifying programs
+/// like the challenge legitimately produce some of these, since the walk
+/// only sees the code as it is stored.
+pub fn misaligned_targets(memory: &[u16]) -> Vec<(u16, u16, u16)> {
+    let code = reachable_code(memory);
+    let mut inside: BTreeMap<u16, u16> = BTreeMap::new();
+    for (&addr, instr) in &code {
+        for offset in 1..instr.size() {
+            inside.insert(addr + offset, addr);
+        }
+    }
+
+    let mut found = Vec::new();
+    for (&addr, instr) in &code {
+        let target = match *instr {
+            Instruction::Jmp(a) | Instruction::Call(a) => literal(a),
+            Instruction::Jt(_, b) | Instruction::Jf(_, b) => literal(b),
+            _ => None,
+        };
+        if let Some(&start) = target.and_then(|target| inside.get(&target)) {
+            found.push((addr, target.unwrap(), start));
+        }
+    }
+    found
+}
+
+/// An instruction and the address it was decoded at, as exported by
+/// `write_decoded`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Decoded {
+    pub address: u16,
+    pub instruction: Instruction,
+}
+
+/// Writes `code` as a stream of records, each the length of its JSON
+/// encoding as a little-endian u32 followed by that JSON, e.g.
+/// `{"address":5489,"instruction":{"Call":6027}}`. Reading a record back
+/// means reading the length, then deserializing a `Decoded` from that many
+/// bytes.
+#[cfg(feature = "serde")]
+pub fn write_decoded<W: std::io::Write>(
+    code: &BTreeMap<u16, Instruction>,
+    out: &mut W,
+) -> std::io::Result<()> {
+    use byteorder::{LittleEndian, WriteBytesExt};
+
+    for (&address, &instruction) in code {
+        let record = serde_json::to_vec(&Decoded {
+            address,
+            instruction,
+        })?;
+        out.write_u32::<LittleEndian>(record.len() as u32)?;
+        out.write_all(&record)?;
+    }
+    out.flush()
+}
+
+/// Linear sweep disassembly: decodes instruction after instruction from
+/// address 0, stepping over single words that do not decode. Data mixed into
+/// the code shows up as whatever instructions it happens to decode to.
+pub fn disassemble(memory: &[u16]) -> BTreeMap<u16, Instruction> {
+    let mut code: BTreeMap<u16, Instruction> = BTreeMap::new();
+    let mut addr: usize = 0;
+
+    while addr < memory.len() {
+        match decode_at(memory, addr) {
+            Some(instr) => {
+                code.insert(addr as u16, instr);
+                addr += instr.size() as usize;
+            }
+            None => addr += 1,
+        }
+    }
+
+    code
+}
+
+/// Literal targets of every `call` in the disassembly, i.e. the subroutine
+/// entry points of the program.
+pub fn find_call_targets(memory: &[u16]) -> BTreeSet<u16> {
+    disassemble(memory)
+        .values()
+        .filter_map(|instr| match instr {
+            Instruction::Call(a) => literal(*a),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Addresses of `call`s through a register, whose targets are not known
+/// statically.
+pub fn find_indirect_calls(memory: &[u16]) -> BTreeSet<u16> {
+    disassemble(memory)
+        .iter()
+        .filter(|(_, instr)| match instr {
+            Instruction::Call(a) => literal(*a).is_none(),
+            _ => false,
+        })
+        .map(|(&addr, _)| addr)
+        .collect()
+}
+
+/// Code addresses of `wmem` instructions with the literal address `target`,
+/// and of `set`s whose destination is that address. The spec only allows
+/// registers as `set` destinations, so those only show up in data decoded as
+/// code.
+pub fn find_writers(memory: &[u16], target: u16) -> Vec<usize> {
+    disassemble(memory)
+        .iter()
+        .filter(|(_, instr)| match instr {
+            Instruction::Wmem(a, _) | Instruction::Set(a, _) => *a == target,
+            _ => false,
+        })
+        .map(|(&addr, _)| addr as usize)
+        .collect()
+}
+
+/// Addresses of `wmem`s through a register, which could write anywhere.
+pub fn find_indirect_writes(memory: &[u16]) -> BTreeSet<u16> {
+    disassemble(memory)
+        .iter()
+        .filter(|(_, instr)| match instr {
+            Instruction::Wmem(a, _) => literal(*a).is_none(),
+            _ => false,
+        })
+        .map(|(&addr, _)| addr)
+        .collect()
+}
+
+/// What `instr` computes, with register sources replaced by their values in
+/// `registers`. `None` if the instruction reads no registers.
+fn annotation(instr: Instruction, registers: &[u16; NUMBER_OF_REGISTERS]) -> Option<String> {
+    let sources = instr.sources();
+    if sources.iter().all(|&oprnd| literal(oprnd).is_some()) {
+        return None;
+    }
+    let value = |oprnd: u16| match get_addr(oprnd, NUMBER_OF_REGISTERS) {
+        Some(Address::Reg(reg)) => registers[reg].to_string(),
+        _ => Oprnd(oprnd).to_string(),
+    };
+    let text = match instr {
+        Instruction::Set(a, b) => format!("{} = {}", Oprnd(a), value(b)),
+        Instruction::Not(a, b) => format!("{} = ~{}", Oprnd(a), value(b)),
+        Instruction::Rmem(a, b) => format!("{} = mem[{}]", Oprnd(a), value(b)),
+        Instruction::Wmem(a, b) => format!("mem[{}] = {}", value(a), value(b)),
+        Instruction::Eq(a, b, c)
+        | Instruction::Gt(a, b, c)
+        | Instruction::Add(a, b, c)
+        | Instruction::Mult(a, b, c)
+        | Instruction::Mod(a, b, c)
+        | Instruction::And(a, b, c)
+        | Instruction::Or(a, b, c) => {
+            let symbol = match instr {
+                Instruction::Eq(_, _, _) => "==",
+                Instruction::Gt(_, _, _) => ">",
+                Instruction::Add(_, _, _) => "+",
+                Instruction::Mult(_, _, _) => "*",
+                Instruction::Mod(_, _, _) => "%",
+                Instruction::And(_, _, _) => "&",
+                _ => "|",
+            };
+            format!("{} = {} {} {}", Oprnd(a), value(b), symbol, value(c))
+        }
+        _ => {
+            let resolved: Vec<String> = sources
+                .iter()
+                .filter(|&&oprnd| literal(oprnd).is_none())
+                .map(|&oprnd| format!("{}={}", Oprnd(oprnd), value(oprnd)))
+                .collect();
+            resolved.join(", ")
+        }
+    };
+    Some(text)
+}
+
+/// One line of a listing. Given a register snapshot, register operands are
+/// resolved in a trailing comment, e.g. `add r0 r1 r2  ; r0 = 3 + 5`.
+pub fn listing_line(
+    addr: u16,
+    instr: Instruction,
+    registers: Option<&[u16; NUMBER_OF_REGISTERS]>,
+    symbols: &Symbols,
+) -> String {
+    let text = symbolic(instr, |addr| symbols.get(&addr));
+    match registers.and_then(|registers| annotation(instr, registers)) {
+        Some(note) => format!("{:5}: {}  ; {}", addr, text, note),
+        None => format!("{:5}: {}", addr, text),
+    }
+}
+
+/// Lists `count` instructions decoded linearly from `start`, each preceded
+/// by its label if `symbols` names it. Words that do not decode are shown as
+/// `<invalid N>` and skipped one at a time.
+pub fn listing(
+    memory: &[u16],
+    start: u16,
+    count: usize,
+    registers: Option<&[u16; NUMBER_OF_REGISTERS]>,
+    symbols: &Symbols,
+) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut listed = 0;
+    let mut addr = start as usize;
+    while listed < count && addr < memory.len() {
+        if let Some(label) = symbols.get(&(addr as u16)) {
+            lines.push(format!("{}:", label));
+        }
+        match decode_at(memory, addr) {
+            Some(instr) => {
+                lines.push(listing_line(addr as u16, instr, registers, symbols));
+                addr += instr.size() as usize;
+            }
+            None => {
+                lines.push(format!("{:5}: <invalid {}>", addr, memory[addr]));
+                addr += 1;
+            }
+        }
+        listed += 1;
+    }
+    lines
+}
+
+/// `instr` as text, with the address it refers to replaced by its name if
+/// `name` knows one, e.g. `call teleport_check`.
+pub fn symbolic<'a, F>(instr: Instruction, name: F) -> String
+where
+    F: Fn(u16) -> Option<&'a String>,
+{
+    let mut text = instr.name().to_string();
+    for (index, oprnd) in instr.operands().into_iter().enumerate() {
+        match name(oprnd) {
+            Some(label) if referenced_operand(instr) == Some(index) => {
+                text.push(' ');
+                text.push_str(label);
+            }
+            _ => text.push_str(&format!(" {}", Oprnd(oprnd))),
+        }
+    }
+    text
+}
+
+/// The operand of `instr` that holds an address other code may be labeled
+/// at: a jump or call target, or what `set` and `rmem` load.
+fn referenced_operand(instr: Instruction) -> Option<usize> {
+    match instr {
+        Instruction::Jmp(_) | Instruction::Call(_) => Some(0),
+        Instruction::Jt(_, _) | Instruction::Jf(_, _) => Some(1),
+        Instruction::Set(_, _) | Instruction::Rmem(_, _) => Some(1),
+        _ => None,
+    }
+}
+
+/// Labels for subroutine entries (`sub_1518`), other jump targets
+/// (`loc_0a2b`) and data strings (`str_0a40`), named after their address in
+/// hex.
+pub fn labels(memory: &[u16]) -> BTreeMap<u16, String> {
+    let mut labels = BTreeMap::new();
+    for block in string_map(memory, MIN_STRING_LEN) {
+        if block.kind == StringKind::Data {
+            labels.insert(block.start, format!("str_{:04x}", block.start));
+        }
+    }
+    for instr in disassemble(memory).values() {
+        let target = match *instr {
+            Instruction::Jmp(a) | Instruction::Jt(_, a) | Instruction::Jf(_, a) => literal(a),
+            _ => None,
+        };
+        if let Some(target) = target {
+            labels
+                .entry(target)
+                .or_insert_with(|| format!("loc_{:04x}", target));
+        }
+    }
+    for target in find_call_targets(memory) {
+        labels.insert(target, format!("sub_{:04x}", target));
+    }
+    labels
+}
+
+/// A disassembly of the whole program with `labels` placed at their
+/// addresses and used in place of the numbers they stand for, so `call 5400`
+/// reads `call sub_1518`. Each label lists the instructions referring to it.
+/// Data strings are shown as text instead of the instructions they happen to
+/// decode to. Names from `symbols` take precedence over generated labels.
+pub fn labeled_listing(memory: &[u16], symbols: &Symbols) -> Vec<String> {
+    let code = disassemble(memory);
+    let mut labels = labels(memory);
+    labels.extend(symbols.iter().map(|(&addr, label)| (addr, label.clone())));
+    let strings: BTreeMap<u16, StringBlock> = string_map(memory, MIN_STRING_LEN)
+        .into_iter()
+        .filter(|block| block.kind == StringKind::Data)
+        .map(|block| (block.start, block))
+        .collect();
+
+    let mut xrefs: BTreeMap<u16, Vec<u16>> = BTreeMap::new();
+    for (&addr, &instr) in &code {
+        if let Some(index) = referenced_operand(instr) {
+            let oprnd = instr.operands()[index];
+            if labels.contains_key(&oprnd) {
+                xrefs.entry(oprnd).or_default().push(addr);
+            }
+        }
+    }
+
+    let mut lines = Vec::new();
+    let mut addr: usize = 0;
+    while addr < memory.len() {
+        let here = addr as u16;
+        if let Some(label) = labels.get(&here) {
+            match xrefs.get(&here) {
+                Some(refs) => {
+                    let refs: Vec<String> = refs.iter().map(|r| r.to_string()).collect();
+                    lines.push(format!("{}:  ; from {}", label, refs.join(", ")));
+                }
+                None => lines.push(format!("{}:", label)),
+            }
+        }
+        if let Some(block) = strings.get(&here) {
+            lines.push(format!("{:5}: data {:?}", addr, block.text));
+            addr = block.end as usize + 1;
+            continue;
+        }
+        match code.get(&here) {
+            Some(&instr) => {
+                let text = symbolic(instr, |oprnd| labels.get(&oprnd));
+                lines.push(format!("{:5}: {}", addr, text));
+                addr += instr.size() as usize;
+            }
+            None => addr += 1,
+        }
+    }
+    lines
+}
+
+/// Runs of at least `min_len` printable words anywhere in memory.
+pub fn scan_strings(memory: &[u16], min_len: usize) -> Vec<StringBlock> {
+    let mut blocks = Vec::new();
+    let mut start: Option<usize> = None;
+
+    for addr in 0..=memory.len() {
+        let ch = memory.get(addr).copied().and_then(printable);
+        match (ch, start) {
+            (Some(_), None) => start = Some(addr),
+            (None, Some(begin)) => {
+                if addr - begin >= min_len {
+                    blocks.push(StringBlock {
+                        kind: StringKind::Data,
+                        start: begin as u16,
+                        end: (addr - 1) as u16,
+                        text: memory[begin..addr]
+                            .iter()
+                            .filter_map(|&word| printable(word))
+                            .collect(),
+                        refs: Vec::new(),
+                    });
+                }
+                start = None;
+            }
+            _ => {}
+        }
+    }
+
+    blocks
+}
+
+/// Runs of at least `min_len` back-to-back `out` instructions with printable
+/// literal operands, which is how the challenge prints most of its text.
+pub fn scan_out_strings(code: &BTreeMap<u16, Instruction>, min_len: usize) -> Vec<StringBlock> {
+    let mut blocks = Vec::new();
+    let mut current: Option<StringBlock> = None;
+
+    for (&addr, &instr) in code {
+        let ch = match instr {
+            Instruction::Out(a) => literal(a).and_then(printable),
+            _ => None,
+        };
+        current = match (ch, current.take()) {
+            (Some(ch), Some(mut block)) if block.end + 1 == addr => {
+                block.end = addr + 1;
+                block.text.push(ch);
+                Some(block)
+            }
+            (ch, block) => {
+                blocks.extend(block.filter(|block| block.text.len() >= min_len));
+                ch.map(|ch| StringBlock {
+                    kind: StringKind::Out,
+                    start: addr,
+                    end: addr + 1,
+                    text: ch.to_string(),
+                    refs: Vec::new(),
+                })
+            }
+        };
+    }
+    blocks.extend(current.filter(|block| block.text.len() >= min_len));
+
+    blocks
+}
+
+/// All strings found in memory, sorted by address. Data strings carry the
+/// addresses of reachable `rmem` and `set` instructions whose literal operand
+/// points into them.
+pub fn string_map(memory: &[u16], min_len: usize) -> Vec<StringBlock> {
+    let code = reachable_code(memory);
+    let mut blocks = scan_strings(memory, min_len);
+
+    for block in blocks.iter_mut() {
+        block.refs = code
+            .iter()
+            .filter(|(_, instr)| match instr {
+                Instruction::Rmem(_, b) | Instruction::Set(_, b) => {
+                    literal(*b).is_some_and(|b| block.contains(b))
+                }
+                _ => false,
+            })
+            .map(|(&addr, _)| addr)
+            .collect();
+    }
+
+    blocks.extend(scan_out_strings(&code, min_len));
+    blocks.sort_by_key(|block| block.start);
+    blocks
+}
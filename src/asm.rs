@@ -0,0 +1,302 @@
+use crate::{opcode_arity, parse_number, ADDRESS_RANGE, INTEGER_RANGE, NUMBER_OF_REGISTERS};
+use crate::{MEMORY_SIZE, OPCODE_NAMES};
+use std::collections::HashMap;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+/// One source line split into its parts, comments already removed.
+struct Line<'a> {
+    number: usize,
+    label: Option<&'a str>,
+    mnemonic: Option<&'a str>,
+    operands: Vec<&'a str>,
+}
+
+fn error<T>(line: usize, message: String) -> Result<T, AsmError> {
+    Err(AsmError { line, message })
+}
+
+fn strip_comment(text: &str) -> &str {
+    let mut quoted = false;
+    for (i, ch) in text.char_indices() {
+        match ch {
+            '\'' => quoted = !quoted,
+            ';' if !quoted => return &text[..i],
+            _ => {}
+        }
+    }
+    text
+}
+
+/// Splits operands on whitespace and commas, keeping `' '` in one piece.
+fn split_operands(text: &str) -> Vec<&str> {
+    let mut operands = Vec::new();
+    let mut start: Option<usize> = None;
+    let mut quoted = false;
+    for (i, ch) in text.char_indices() {
+        match ch {
+            '\'' => {
+                quoted = !quoted;
+                start.get_or_insert(i);
+            }
+            ch if !quoted && (ch.is_whitespace() || ch == ',') => {
+                if let Some(begin) = start.take() {
+                    operands.push(&text[begin..i]);
+                }
+            }
+            _ => {
+                start.get_or_insert(i);
+            }
+        }
+    }
+    if let Some(begin) = start {
+        operands.push(&text[begin..]);
+    }
+    operands
+}
+
+fn parse_line(number: usize, text: &str) -> Line<'_> {
+    let mut text = strip_comment(text).trim();
+    let mut label = None;
+    if let Some((name, rest)) = text.split_once(':') {
+        if !name.contains('\'') {
+            label = Some(name.trim());
+            text = rest.trim();
+        }
+    }
+    let (mnemonic, rest) = match text.split_once(char::is_whitespace) {
+        Some((mnemonic, rest)) => (mnemonic, rest),
+        None => (text, ""),
+    };
+    Line {
+        number,
+        label,
+        mnemonic: if mnemonic.is_empty() {
+            None
+        } else {
+            Some(mnemonic)
+        },
+        operands: split_operands(rest),
+    }
+}
+
+fn opcode(line: &Line, mnemonic: &str) -> Result<(u16, usize), AsmError> {
+    let lower = mnemonic.to_ascii_lowercase();
+    match OPCODE_NAMES.iter().position(|&name| name == lower) {
+        Some(opcode) => Ok((opcode as u16, opcode_arity(opcode as u16).unwrap() as usize)),
+        None => error(line.number, format!("unknown instruction {}", mnemonic)),
+    }
+}
+
+fn is_label_name(text: &str) -> bool {
+    let name = text.strip_prefix('.').unwrap_or(text);
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(ch) if ch.is_ascii_alphabetic() || ch == '_')
+        && chars.all(|ch| ch.is_ascii_alphanumeric() || ch == '_')
+}
+
+/// Resolves `+N` or `-N` against the address of the instruction it appears
+/// in. Returns `Ok(None)` if `text` is not a relative operand.
+fn relative(line: usize, text: &str, addr: u16) -> Result<Option<u16>, AsmError> {
+    let (sign, offset) = match text.chars().next() {
+        Some(sign @ '+') | Some(sign @ '-') => (sign, &text[1..]),
+        _ => return Ok(None),
+    };
+    let offset = match parse_number(offset) {
+        Some(offset) => offset as i64,
+        None => return error(line, format!("bad relative target {}", text)),
+    };
+    let target = if sign == '+' {
+        addr as i64 + offset
+    } else {
+        addr as i64 - offset
+    };
+    if target < 0 || target >= MEMORY_SIZE as i64 {
+        return error(
+            line,
+            format!("{} from {} lands outside memory at {}", text, addr, target),
+        );
+    }
+    Ok(Some(target as u16))
+}
+
+/// Encodes an operand that is a register, a number or a character literal.
+/// Returns `Ok(None)` for something that looks like a label reference.
+fn encode_value(line: usize, text: &str) -> Result<Option<u16>, AsmError> {
+    let lower = text.to_ascii_lowercase();
+    if let Some(index) = lower
+        .strip_prefix('r')
+        .and_then(|n| n.parse::<usize>().ok())
+    {
+        if index < NUMBER_OF_REGISTERS {
+            return Ok(Some((ADDRESS_RANGE + index) as u16));
+        }
+        return error(line, format!("no such register {}", text));
+    }
+    if text.len() >= 3 && text.starts_with('\'') && text.ends_with('\'') {
+        let inner = &text[1..text.len() - 1];
+        let ch = match inner {
+            "\\n" => '\n',
+            _ if inner.chars().count() == 1 => inner.chars().next().unwrap(),
+            _ => return error(line, format!("bad character literal {}", text)),
+        };
+        return Ok(Some(ch as u16));
+    }
+    if let Some(value) = parse_number(text) {
+        if value < INTEGER_RANGE {
+            return Ok(Some(value as u16));
+        }
+        return error(line, format!("{} does not fit in 15 bits", text));
+    }
+    if is_label_name(text) {
+        return Ok(None);
+    }
+    error(line, format!("bad operand {}", text))
+}
+
+/// Encodes `line`, which starts at `addr` in the program whose labels are
+/// given. Without that context labels and relative targets are rejected.
+fn encode(line: &Line, program: Option<(&HashMap<&str, u16>, u16)>) -> Result<Vec<u16>, AsmError> {
+    let mnemonic = match line.mnemonic {
+        Some(mnemonic) => mnemonic,
+        None => return Ok(Vec::new()),
+    };
+    let (opcode, arity) = opcode(line, mnemonic)?;
+    if line.operands.len() != arity {
+        return error(
+            line.number,
+            format!(
+                "{} takes {} operands, got {}",
+                mnemonic,
+                arity,
+                line.operands.len()
+            ),
+        );
+    }
+
+    let mut words = vec![opcode];
+    for operand in &line.operands {
+        if operand.starts_with('+') || operand.starts_with('-') {
+            let word = match program {
+                Some((_, addr)) => relative(line.number, operand, addr)?.unwrap(),
+                None => {
+                    return error(
+                        line.number,
+                        format!("relative targets like {} need a whole program", operand),
+                    )
+                }
+            };
+            words.push(word);
+            continue;
+        }
+        let word = match (encode_value(line.number, operand)?, program) {
+            (Some(word), _) => word,
+            (None, Some((labels, _))) => match labels.get(operand) {
+                Some(&addr) => addr,
+                None => return error(line.number, format!("undefined label {}", operand)),
+            },
+            (None, None) => {
+                return error(
+                    line.number,
+                    format!("labels like {} need a whole program", operand),
+                )
+            }
+        };
+        words.push(word);
+    }
+    Ok(words)
+}
+
+/// Encodes a single instruction such as `jt r0 6`. Labels cannot be
+/// resolved without the surrounding program and are rejected.
+pub fn encode_line(text: &str) -> Result<Vec<u16>, AsmError> {
+    let line = parse_line(1, text);
+    if let Some(label) = line.label {
+        return error(
+            1,
+            format!("label {} is not allowed in single-line mode", label),
+        );
+    }
+    encode(&line, None)
+}
+
+/// Assembles a program, one instruction per line. A line may start with a
+/// `label:` that operands can refer to by name; `;` starts a comment.
+/// Operands are registers `r0`..`r7`, numbers (decimal or `0x` hex),
+/// character literals like `'A'` or `'\n'`, labels, and `+N`/`-N` targets
+/// counted from the start of the instruction they appear in, so `jmp +0`
+/// loops forever.
+pub fn assemble(source: &str) -> Result<Vec<u16>, AsmError> {
+    let lines: Vec<Line> = source
+        .lines()
+        .enumerate()
+        .map(|(i, text)| parse_line(i + 1, text))
+        .collect();
+
+    let mut labels: HashMap<&str, u16> = HashMap::new();
+    let mut addr: usize = 0;
+    for line in &lines {
+        if let Some(label) = line.label {
+            if !is_label_name(label) {
+                return error(line.number, format!("bad label name {}", label));
+            }
+            if labels.insert(label, addr as u16).is_some() {
+                return error(line.number, format!("label {} defined twice", label));
+            }
+        }
+        if let Some(mnemonic) = line.mnemonic {
+            addr += 1 + opcode(line, mnemonic)?.1;
+        }
+        if addr > MEMORY_SIZE {
+            return error(line.number, String::from("program does not fit in memory"));
+        }
+    }
+
+    let mut words = Vec::with_capacity(addr);
+    for line in &lines {
+        let addr = words.len() as u16;
+        words.extend(encode(line, Some((&labels, addr)))?);
+    }
+    Ok(words)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::Capture;
+    use crate::Machine;
+
+    #[test]
+    fn loop_with_relative_backward_jump() {
+        let source = "set r0 3\nout '*'\nadd r0 r0 32767\njt r0 -6\nhalt";
+        let words = assemble(source).unwrap();
+        assert_eq!(
+            words,
+            vec![1, 32768, 3, 19, 42, 9, 32768, 32768, 32767, 7, 32768, 3, 0]
+        );
+
+        let mut machine = Machine::from_words(&words).unwrap();
+        let capture = Capture::new();
+        machine.output = Box::new(capture.clone());
+        machine.run().unwrap();
+        assert_eq!(capture.bytes(), b"***");
+    }
+
+    #[test]
+    fn relative_jump_before_address_zero_is_rejected() {
+        assert_eq!(assemble("noop\njmp -2").unwrap_err().line, 2);
+    }
+}
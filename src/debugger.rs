@@ -0,0 +1,765 @@
+use crate::analysis::{disassemble, listing, listing_line, next_addresses, Successor};
+use crate::journal::Journal;
+use crate::output::Scrollback;
+use crate::symbols::{self, Symbols};
+use crate::{
+    decode_at, get_op, get_oprnd_value, load_file, parse_number, read_mem, Address, Change,
+    Instruction, Machine, Oprnd, VmError, INTEGER_RANGE, NUMBER_OF_REGISTERS,
+};
+use std::collections::BTreeSet;
+use std::convert::TryFrom;
+use std::fs::{self, File};
+use std::io::{self, stdin, BufReader, Write};
+
+const HELP: &str = "\
+step [n]         execute n instructions (default 1)
+next             like step, but run a call until it returns
+continue         run until a breakpoint or halt
+until <addr>     run until ip reaches addr, without leaving a breakpoint
+finish           run until the current subroutine returns
+break <addr>     stop before executing the instruction at addr
+delete <addr>    remove the breakpoint at addr
+bout <text>      stop right after the program has printed text
+info breakpoints list breakpoints as the commands that set them
+regs             show ip, registers and stack depth
+stack [n]        show the top n stack entries (default 16)
+setstack <i> <v> overwrite stack entry i (0 is the top) with v
+hash             fingerprint of memory, registers, stack and ip
+x <addr> [n]     hex dump n words from addr (default 16)
+explain          show how the operands at ip resolve and what will happen
+succ <addr>      list where execution can go after the instruction at addr
+list [n]         disassemble n instructions from ip with registers resolved (default 10)
+output [n]       show the last n lines the program printed (default 20)
+savecpu <file>   save ip, registers and stack (not memory)
+loadcpu <file>   restore ip, registers and stack saved with savecpu
+reload           load the program file again and start over, keeping breakpoints
+name <addr> [l]  name addr l in listings, or forget its name without l
+savesyms <file>  save the names given to addresses
+loadsyms <file>  replace the names with those saved with savesyms
+try <loc>=<v>    write v to an address or register and run until input
+revert           undo the write made by the last try
+undo [n]         step back n instructions (default 1)
+redo [n]         step forward again through n undone instructions
+quit             leave the debugger";
+
+/// Why execution handed control back to the debugger.
+enum Pause {
+    Breakpoint,
+    Input,
+    Output(String),
+    Steps,
+    Returned,
+    Reached,
+    Halted,
+}
+
+/// `finish` and `until` give up after this many instructions, in case the
+/// routine never returns or juggles the stack so its `ret` is not
+/// recognised, or the address is never reached.
+const FINISH_STEP_LIMIT: u64 = 10_000_000;
+
+/// An interactive debugger driving a `Machine` from stdin commands.
+pub struct Debugger {
+    pub breakpoints: BTreeSet<u16>,
+    pub output_breakpoints: Vec<String>,
+    /// Everything executed from the debugger goes through the journal, so
+    /// the last `journal::DEFAULT_DEPTH` instructions can be undone.
+    pub journal: Journal,
+    /// Show registers and stack as well when the program halts.
+    pub pause_on_halt: bool,
+    /// The write made by the last `try` and the value it replaced, for
+    /// `revert`.
+    tried: Option<(Address, u16, u16)>,
+    /// Names for addresses, shown in listings and accepted wherever a
+    /// command takes an address.
+    pub symbols: Symbols,
+    /// The file `reload` reads the program from.
+    pub program: Option<String>,
+    /// Where the `until` in progress stops.
+    until: Option<u16>,
+    /// Recent program output for `output`, if the machine's output is
+    /// copied into it.
+    pub scrollback: Option<Scrollback>,
+}
+
+impl Default for Debugger {
+    fn default() -> Debugger {
+        Debugger::new()
+    }
+}
+
+impl Debugger {
+    pub fn new() -> Debugger {
+        Debugger {
+            breakpoints: BTreeSet::new(),
+            output_breakpoints: Vec::new(),
+            journal: Journal::default(),
+            pause_on_halt: false,
+            tried: None,
+            symbols: Symbols::new(),
+            program: None,
+            until: None,
+            scrollback: None,
+        }
+    }
+
+    /// Runs the command loop until `quit`, the end of stdin or an error
+    /// reading it, which is reported on stderr.
+    pub fn repl(&mut self, machine: &mut Machine) -> Result<(), VmError> {
+        show_position(machine, &self.symbols);
+        loop {
+            print!("(dbg) ");
+            let _ = io::stdout().flush();
+            let mut line = String::new();
+            match stdin().read_line(&mut line) {
+                Ok(0) => return Ok(()),
+                Ok(_) => {}
+                Err(err) => {
+                    eprintln!("cannot read a command: {}", err);
+                    return Ok(());
+                }
+            }
+            if !self.command(machine, line.trim_end_matches(['\r', '\n']))? {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Executes one debugger command. Returns `Ok(false)` when the session
+    /// should end.
+    pub fn command(&mut self, machine: &mut Machine, line: &str) -> Result<bool, VmError> {
+        let (cmd, rest) = match line.trim_start().split_once(' ') {
+            Some((cmd, rest)) => (cmd, rest),
+            None => (line.trim(), ""),
+        };
+        let arg = rest.trim();
+
+        match cmd {
+            "" => {}
+            "help" | "h" => println!("{}", HELP),
+            "quit" | "q" => return Ok(false),
+            "step" | "s" => match count_arg(arg) {
+                Some(count) => self.resume(machine, Some(count), None, false)?,
+                None => println!("usage: step [n]"),
+            },
+            // Stepping over a call is stepping into it and finishing it:
+            // the return is recognised by the stack dropping below the
+            // depth right after the call, so recursive calls made inside
+            // do not stop it early.
+            "next" | "n" => match get_op(machine) {
+                Some(Instruction::Call(_)) => {
+                    let depth = machine.stack_depth() + 1;
+                    self.resume(machine, Some(FINISH_STEP_LIMIT), Some(depth), false)?
+                }
+                _ => self.resume(machine, Some(1), None, false)?,
+            },
+            "continue" | "c" => self.resume(machine, None, None, false)?,
+            "until" => match symbols::resolve(&self.symbols, arg) {
+                Some(addr) => {
+                    self.until = Some(addr);
+                    let result = self.resume(machine, Some(FINISH_STEP_LIMIT), None, false);
+                    self.until = None;
+                    result?
+                }
+                None => println!("usage: until <addr>"),
+            },
+            "try" => match self.parse_location(arg) {
+                Some((location, value)) => {
+                    let old = read_mem(machine, location);
+                    self.journal.patch(
+                        machine,
+                        Change::Write {
+                            address: location,
+                            old,
+                            new: value,
+                        },
+                    );
+                    self.tried = Some((location, old, value));
+                    println!("{} = {} (was {})", location_name(location), value, old);
+                    self.resume(machine, None, None, true)?;
+                }
+                None => println!("usage: try <addr or register>=<value>"),
+            },
+            "revert" => match self.tried.take() {
+                Some((location, old, value)) => {
+                    let current = read_mem(machine, location);
+                    self.journal.patch(
+                        machine,
+                        Change::Write {
+                            address: location,
+                            old: current,
+                            new: old,
+                        },
+                    );
+                    println!(
+                        "{} = {} again (was {}, tried {})",
+                        location_name(location),
+                        old,
+                        current,
+                        value
+                    );
+                }
+                None => println!("nothing to revert"),
+            },
+            "finish" | "f" => match machine.stack_depth() {
+                0 => println!("the stack is empty, not inside a subroutine"),
+                depth => self.resume(machine, Some(FINISH_STEP_LIMIT), Some(depth), false)?,
+            },
+            "break" | "b" => match symbols::resolve(&self.symbols, arg) {
+                Some(addr) => {
+                    self.breakpoints.insert(addr);
+                    println!("breakpoint at {}", symbols::describe(&self.symbols, addr));
+                }
+                None => println!("usage: break <addr>"),
+            },
+            "delete" | "d" => match symbols::resolve(&self.symbols, arg) {
+                Some(addr) if self.breakpoints.remove(&addr) => println!(
+                    "deleted breakpoint at {}",
+                    symbols::describe(&self.symbols, addr)
+                ),
+                Some(addr) => println!("no breakpoint at {}", addr),
+                None => println!("usage: delete <addr>"),
+            },
+            "bout" => {
+                if rest.is_empty() {
+                    println!("usage: bout <text>");
+                } else {
+                    self.output_breakpoints.push(rest.to_string());
+                    println!("output breakpoint on {:?}", rest);
+                }
+            }
+            "info" if matches!(arg, "breakpoints" | "b") => {
+                if self.breakpoints.is_empty() && self.output_breakpoints.is_empty() {
+                    println!("no breakpoints");
+                }
+                for &addr in &self.breakpoints {
+                    println!("break {}", symbols::describe(&self.symbols, addr));
+                }
+                for text in &self.output_breakpoints {
+                    println!("bout {}", text);
+                }
+            }
+            "info" => println!("usage: info breakpoints"),
+            "regs" | "r" => show_registers(machine),
+            "stack" => match parse_number(if arg.is_empty() { "16" } else { arg }) {
+                Some(count) => show_stack(machine, count),
+                None => println!("usage: stack [n]"),
+            },
+            "setstack" => {
+                let parsed = arg.split_once(' ').and_then(|(index, value)| {
+                    Some((parse_number(index)?, parse_number(value.trim())?))
+                });
+                match parsed {
+                    Some((_, value)) if value >= INTEGER_RANGE => {
+                        println!("{} does not fit in 15 bits", value)
+                    }
+                    Some((index, value)) => match machine.set_stack_entry(index, value as u16) {
+                        Some(old) => {
+                            println!("#{} {} -> {}", index, old, value);
+                            println!("note: a wrong return address derails the next ret");
+                        }
+                        None => println!("the stack has only {} entries", machine.stack_depth()),
+                    },
+                    None => println!("usage: setstack <index> <value>"),
+                }
+            }
+            "hash" => println!("{:016x}", machine.state_hash()),
+            "explain" | "e" => explain(machine),
+            "x" => {
+                let (addr, count) = match arg.split_once(' ') {
+                    Some((addr, count)) => (addr, parse_number(count.trim())),
+                    None => (arg, Some(16)),
+                };
+                match (symbols::resolve(&self.symbols, addr), count) {
+                    (Some(addr), Some(count)) => hex_dump(&machine.memory, addr as usize, count),
+                    _ => println!("usage: x <addr> [count]"),
+                }
+            }
+            "succ" => match symbols::resolve(&self.symbols, arg) {
+                Some(addr) => successors(&machine.memory, addr, &self.symbols),
+                None => println!("usage: succ <addr>"),
+            },
+            "list" | "l" => match parse_number(if arg.is_empty() { "10" } else { arg }) {
+                Some(count) => {
+                    let registers =
+                        <&[u16; NUMBER_OF_REGISTERS]>::try_from(&machine.registers[..]).ok();
+                    let lines =
+                        listing(&machine.memory, machine.ip, count, registers, &self.symbols);
+                    for line in lines {
+                        println!("{}", line);
+                    }
+                }
+                None => println!("usage: list [n]"),
+            },
+            "output" => match (
+                &self.scrollback,
+                parse_number(if arg.is_empty() { "20" } else { arg }),
+            ) {
+                (None, _) => println!("no output is kept"),
+                (Some(scrollback), Some(count)) => {
+                    let lines = scrollback.lines();
+                    for line in &lines[lines.len().saturating_sub(count)..] {
+                        println!("{}", line);
+                    }
+                    let partial = scrollback.partial();
+                    if !partial.is_empty() {
+                        println!("{}", partial);
+                    }
+                }
+                (Some(_), None) => println!("usage: output [n]"),
+            },
+            "savecpu" if !arg.is_empty() => {
+                match File::create(arg).and_then(|mut file| machine.save_cpu_state(&mut file)) {
+                    Ok(()) => println!("saved cpu state to {}", arg),
+                    Err(err) => println!("{}: {}", arg, err),
+                }
+            }
+            "loadcpu" if !arg.is_empty() => {
+                match File::open(arg)
+                    .and_then(|file| machine.load_cpu_state(&mut BufReader::new(file)))
+                {
+                    Ok(()) => {
+                        self.journal.clear();
+                        show_position(machine, &self.symbols);
+                    }
+                    Err(err) => println!("{}: {}", arg, err),
+                }
+            }
+            "savecpu" | "loadcpu" => println!("usage: {} <file>", cmd),
+            "reload" => match self.program.as_deref().map(|path| (path, load_file(path))) {
+                Some((_, Ok(memory))) => {
+                    machine.reset(memory);
+                    self.journal.clear();
+                    self.tried = None;
+                    self.check_breakpoints(&machine.memory);
+                    show_position(machine, &self.symbols);
+                }
+                Some((path, Err(err))) => println!("{}: {}", path, err),
+                None => println!("no program file to reload"),
+            },
+            "name" => {
+                let (addr, label) = match arg.split_once(' ') {
+                    Some((addr, label)) => (addr, label.trim()),
+                    None => (arg, ""),
+                };
+                match symbols::resolve(&self.symbols, addr) {
+                    Some(addr) if label.is_empty() => match self.symbols.remove(&addr) {
+                        Some(label) => println!("{} is no longer named {}", addr, label),
+                        None => println!("{} has no name", addr),
+                    },
+                    Some(addr) if symbols::valid_label(label) => {
+                        self.symbols.retain(|_, existing| existing != label);
+                        self.symbols.insert(addr, label.to_string());
+                        println!("{} is now {}", addr, label);
+                    }
+                    Some(_) => println!("invalid label {:?}", label),
+                    None => println!("usage: name <addr> [label]"),
+                }
+            }
+            "savesyms" if !arg.is_empty() => {
+                match File::create(arg).and_then(|mut file| symbols::save(&self.symbols, &mut file))
+                {
+                    Ok(()) => println!("saved {} names to {}", self.symbols.len(), arg),
+                    Err(err) => println!("{}: {}", arg, err),
+                }
+            }
+            "loadsyms" if !arg.is_empty() => match symbols::from_file(arg) {
+                Ok(loaded) => {
+                    self.symbols = loaded;
+                    println!("loaded {} names from {}", self.symbols.len(), arg);
+                }
+                Err(err) => println!("{}: {}", arg, err),
+            },
+            "savesyms" | "loadsyms" => println!("usage: {} <file>", cmd),
+            "undo" | "u" => match count_arg(arg) {
+                Some(count) => {
+                    let undone = (0..count)
+                        .take_while(|_| self.journal.undo(machine))
+                        .count();
+                    println!("undid {} instructions", undone);
+                    show_position(machine, &self.symbols);
+                }
+                None => println!("usage: undo [n]"),
+            },
+            "redo" => match count_arg(arg) {
+                Some(count) => {
+                    let redone = (0..count)
+                        .take_while(|_| self.journal.redo(machine))
+                        .count();
+                    println!("redid {} instructions", redone);
+                    show_position(machine, &self.symbols);
+                }
+                None => println!("usage: redo [n]"),
+            },
+            _ => println!("unknown command {:?}, try help", cmd),
+        }
+        Ok(true)
+    }
+
+    /// The `break` and `bout` commands that recreate the current
+    /// breakpoints, as saved by `save_breakpoints`.
+    pub fn breakpoint_commands(&self) -> Vec<String> {
+        let mut commands: Vec<String> = self
+            .breakpoints
+            .iter()
+            .map(|addr| format!("break {}", addr))
+            .collect();
+        for text in &self.output_breakpoints {
+            commands.push(format!("bout {}", text));
+        }
+        commands
+    }
+
+    pub fn save_breakpoints(&self, path: &str) -> io::Result<()> {
+        let mut out = File::create(path)?;
+        for command in self.breakpoint_commands() {
+            writeln!(out, "{}", command)?;
+        }
+        out.flush()
+    }
+
+    /// Adds the breakpoints listed in `path`, one `break` or `bout` command
+    /// per line. Any other command is an error.
+    pub fn load_breakpoints(&mut self, machine: &mut Machine, path: &str) -> io::Result<()> {
+        let text = fs::read_to_string(path)?;
+        for (number, line) in text.lines().enumerate() {
+            let cmd = line.split_whitespace().next().unwrap_or("");
+            if !matches!(cmd, "" | "break" | "b" | "bout") {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("line {}: not a breakpoint: {}", number + 1, line),
+                ));
+            }
+            let _ = self.command(machine, line);
+        }
+        Ok(())
+    }
+
+    /// Warns about breakpoints that a linear disassembly of `memory` does
+    /// not put at the start of an instruction, after the program changed.
+    fn check_breakpoints(&self, memory: &[u16]) {
+        let code = disassemble(memory);
+        for &addr in &self.breakpoints {
+            if !code.contains_key(&addr) {
+                println!(
+                    "warning: breakpoint at {} is not at an instruction",
+                    symbols::describe(&self.symbols, addr)
+                );
+            }
+        }
+    }
+
+    /// `loc=value` as given to `try`, where `loc` is an address, a label or
+    /// a register like `r7`.
+    fn parse_location(&self, arg: &str) -> Option<(Address, u16)> {
+        let (location, value) = arg.split_once('=')?;
+        let location = match location.trim().strip_prefix('r').map(str::parse::<usize>) {
+            Some(Ok(reg)) if reg < NUMBER_OF_REGISTERS => Address::Reg(reg),
+            _ => Address::Mem(symbols::resolve(&self.symbols, location.trim())? as usize),
+        };
+        let value = parse_number(value.trim()).filter(|&value| value < INTEGER_RANGE)?;
+        Some((location, value as u16))
+    }
+
+    fn resume(
+        &mut self,
+        machine: &mut Machine,
+        steps: Option<u64>,
+        return_depth: Option<usize>,
+        until_input: bool,
+    ) -> Result<(), VmError> {
+        let pause = self.execute(machine, steps, return_depth, until_input)?;
+        match pause {
+            Pause::Halted if self.pause_on_halt => {
+                println!("halted");
+                show_registers(machine);
+                show_stack(machine, 16);
+            }
+            Pause::Halted => println!("halted"),
+            Pause::Breakpoint => println!("breakpoint"),
+            Pause::Input => println!("\nwaiting for input"),
+            Pause::Output(text) => println!("\noutput breakpoint {:?}", text),
+            Pause::Returned => println!("returned"),
+            Pause::Reached => println!("reached"),
+            Pause::Steps if return_depth.is_some() => {
+                println!("no return after {} instructions", FINISH_STEP_LIMIT)
+            }
+            Pause::Steps if self.until.is_some() => println!(
+                "{} not reached after {} instructions",
+                symbols::describe(&self.symbols, self.until.unwrap()),
+                FINISH_STEP_LIMIT
+            ),
+            Pause::Steps => {}
+        }
+        show_position(machine, &self.symbols);
+        Ok(())
+    }
+
+    /// Steps the machine until a pause condition. Breakpoints are checked
+    /// before each instruction except the first, so resuming from a
+    /// breakpoint makes progress, and so is the target of `until`. With
+    /// `return_depth`, a `ret` that leaves fewer entries than that on the
+    /// stack pauses too, and with `until_input` reaching an `in` that has no
+    /// input to read.
+    fn execute(
+        &mut self,
+        machine: &mut Machine,
+        steps: Option<u64>,
+        return_depth: Option<usize>,
+        until_input: bool,
+    ) -> Result<Pause, VmError> {
+        let mut executed: u64 = 0;
+        loop {
+            if steps == Some(executed) {
+                return Ok(Pause::Steps);
+            }
+            if until_input && executed > 0 && machine.needs_input() {
+                return Ok(Pause::Input);
+            }
+            if executed > 0 && self.until == Some(machine.ip) {
+                return Ok(Pause::Reached);
+            }
+            if executed > 0 && self.breakpoints.contains(&machine.ip) {
+                return Ok(Pause::Breakpoint);
+            }
+            let instr = get_op(machine);
+            if !self.journal.step(machine)? {
+                return Ok(Pause::Halted);
+            }
+            executed += 1;
+            if let (Some(Instruction::Ret), Some(depth)) = (instr, return_depth) {
+                if machine.stack_depth() < depth {
+                    return Ok(Pause::Returned);
+                }
+            }
+            if let Some(Instruction::Out(_)) = instr {
+                if let Some(text) = self
+                    .output_breakpoints
+                    .iter()
+                    .find(|text| machine.output_ends_with(text))
+                {
+                    return Ok(Pause::Output(text.clone()));
+                }
+            }
+        }
+    }
+}
+
+fn location_name(location: Address) -> String {
+    match location {
+        Address::Reg(reg) => format!("r{}", reg),
+        Address::Mem(addr) => format!("mem[{}]", addr),
+    }
+}
+
+fn count_arg(arg: &str) -> Option<u64> {
+    if arg.is_empty() {
+        Some(1)
+    } else {
+        parse_number(arg).map(|count| count as u64)
+    }
+}
+
+/// Prints ip, the instruction there, registers and the top of the stack.
+pub fn show_state(machine: &Machine, symbols: &Symbols) {
+    show_position(machine, symbols);
+    show_registers(machine);
+    show_stack(machine, 16);
+}
+
+fn show_position(machine: &Machine, symbols: &Symbols) {
+    if let Some(label) = symbols.get(&machine.ip) {
+        println!("{}:", label);
+    }
+    match get_op(machine) {
+        Some(instr) => println!("{}", listing_line(machine.ip, instr, None, symbols)),
+        None => println!(
+            "{:5}: <invalid {}>",
+            machine.ip, machine.memory[machine.ip as usize]
+        ),
+    }
+}
+
+fn show_registers(machine: &Machine) {
+    let registers: Vec<String> = machine
+        .registers
+        .iter()
+        .enumerate()
+        .map(|(i, value)| format!("r{}={}", i, value))
+        .collect();
+    println!(
+        "ip={} {} stack depth={}",
+        machine.ip,
+        registers.join(" "),
+        machine.stack_depth()
+    );
+}
+
+fn show_stack(machine: &Machine, count: usize) {
+    let stack = machine.stack_snapshot();
+    if stack.is_empty() {
+        println!("stack is empty");
+    }
+    for (index, value) in stack.iter().enumerate().take(count) {
+        println!("#{:<3} {}", index, value);
+    }
+    if stack.len() > count {
+        println!("... {} more", stack.len() - count);
+    }
+}
+
+const DUMP_WORDS_PER_LINE: usize = 8;
+
+/// Prints `count` words from `start`, clamped to memory, as hex with the
+/// printable ones repeated as text on the right.
+fn hex_dump(memory: &[u16], start: usize, count: usize) {
+    let end = start.saturating_add(count).min(memory.len());
+    for line_start in (start..end).step_by(DUMP_WORDS_PER_LINE) {
+        let words = &memory[line_start..(line_start + DUMP_WORDS_PER_LINE).min(end)];
+        let hex: Vec<String> = words.iter().map(|word| format!("{:04x}", word)).collect();
+        let text: String = words
+            .iter()
+            .map(|&word| match word {
+                0x20..=0x7e => word as u8 as char,
+                _ => '.',
+            })
+            .collect();
+        println!(
+            "{:5}: {:width$}  {}",
+            line_start,
+            hex.join(" "),
+            text,
+            width = DUMP_WORDS_PER_LINE * 5 - 1
+        );
+    }
+}
+
+/// Prints the instruction at `addr` and every address execution can go to
+/// next, from the decoded instruction alone.
+fn successors(memory: &[u16], addr: u16, symbols: &Symbols) {
+    let instr = match decode_at(memory, addr as usize) {
+        Some(instr) => instr,
+        None => return println!("no instruction at {}", addr),
+    };
+    println!("{}", listing_line(addr, instr, None, symbols));
+    let next = next_addresses(addr, instr);
+    if next.is_empty() {
+        println!("  none, execution stops");
+    }
+    for successor in next {
+        let (kind, target) = match successor {
+            Successor::FallThrough(target) => ("fall-through", target),
+            Successor::Jump(target) => ("jump", target),
+            Successor::Call(target) => ("call", target),
+            Successor::Return(target) => ("return", target),
+            Successor::Dynamic => {
+                println!("  dynamic (unknown until run)");
+                continue;
+            }
+        };
+        println!("  {} {}", kind, symbols::describe(symbols, target));
+    }
+}
+
+/// Describes the instruction at ip without executing it: each raw operand,
+/// what it resolves to, and the effect, e.g. for `rmem r0 r1`
+///
+/// ```text
+///   a = 32768: register r0, written
+///   b = 32769: register r1, holds 2000
+///   r0 = mem[2000] = 17
+/// ```
+fn explain(machine: &Machine) {
+    let instr = match get_op(machine) {
+        Some(instr) => instr,
+        None => return show_position(machine, &Symbols::new()),
+    };
+    println!("{:5}: {}  ; {}", machine.ip, instr, instr.effect());
+    for (index, oprnd) in instr.operands().into_iter().enumerate() {
+        let role = if index == 0 && instr.destination().is_some() {
+            "written"
+        } else {
+            "read"
+        };
+        let resolved = match machine.address(oprnd) {
+            _ if (oprnd as usize) < INTEGER_RANGE => format!("literal {}", oprnd),
+            Some(Address::Reg(reg)) if role == "written" => format!("register r{}, written", reg),
+            Some(Address::Reg(reg)) => {
+                format!("register r{}, holds {}", reg, machine.registers[reg])
+            }
+            _ => String::from("invalid, neither a number nor a register"),
+        };
+        println!(
+            "  {} = {}: {}",
+            (b'a' + index as u8) as char,
+            oprnd,
+            resolved
+        );
+    }
+    if instr
+        .operands()
+        .iter()
+        .any(|&oprnd| machine.address(oprnd).is_none())
+    {
+        return;
+    }
+    println!("  {}", effect(machine, instr));
+}
+
+/// What `instr` will do, given the machine's current state.
+fn effect(machine: &Machine, instr: Instruction) -> String {
+    let value = |oprnd: u16| get_oprnd_value(machine, oprnd);
+    let next = machine.ip + instr.size();
+    let binary = |a: u16, b: u16, c: u16, symbol: &str, result: usize| {
+        let result = result % INTEGER_RANGE;
+        format!(
+            "{} = {} {} {} = {}",
+            Oprnd(a),
+            value(b),
+            symbol,
+            value(c),
+            result
+        )
+    };
+    let jump = |condition: bool, test: String, target: u16| {
+        if condition {
+            format!("{}, so jump to {}", test, value(target))
+        } else {
+            format!("not taken, continue at {}", next)
+        }
+    };
+    match instr {
+        Instruction::Halt => String::from("stop the machine"),
+        Instruction::Set(a, b) => format!("{} = {}", Oprnd(a), value(b)),
+        Instruction::Push(a) => format!("push {}", value(a)),
+        Instruction::Pop(a) => match machine.stack_snapshot().first() {
+            Some(top) => format!("{} = {}, popped", Oprnd(a), top),
+            None => String::from("pop from an empty stack"),
+        },
+        Instruction::Eq(a, b, c) => binary(a, b, c, "==", (value(b) == value(c)) as usize),
+        Instruction::Gt(a, b, c) => binary(a, b, c, ">", (value(b) > value(c)) as usize),
+        Instruction::Add(a, b, c) => binary(a, b, c, "+", value(b) as usize + value(c) as usize),
+        Instruction::Mult(a, b, c) => binary(a, b, c, "*", value(b) as usize * value(c) as usize),
+        Instruction::Mod(_, b, c) if value(c) == 0 => format!("{} % 0 divides by zero", value(b)),
+        Instruction::Mod(a, b, c) => binary(a, b, c, "%", (value(b) % value(c)) as usize),
+        Instruction::And(a, b, c) => binary(a, b, c, "&", (value(b) & value(c)) as usize),
+        Instruction::Or(a, b, c) => binary(a, b, c, "|", (value(b) | value(c)) as usize),
+        Instruction::Not(a, b) => format!("{} = ~{} = {}", Oprnd(a), value(b), !value(b) & 0x7fff),
+        Instruction::Jmp(a) => format!("jump to {}", value(a)),
+        Instruction::Jt(a, b) => jump(value(a) != 0, format!("{} is nonzero", value(a)), b),
+        Instruction::Jf(a, b) => jump(value(a) == 0, format!("{} is zero", value(a)), b),
+        Instruction::Rmem(a, b) => match machine.memory.get(value(b) as usize) {
+            Some(word) => format!("{} = mem[{}] = {}", Oprnd(a), value(b), word),
+            None => format!("read from {}, outside memory", value(b)),
+        },
+        Instruction::Wmem(a, b) => format!("mem[{}] = {}", value(a), value(b)),
+        Instruction::Call(a) => format!("push return address {}, jump to {}", next, value(a)),
+        Instruction::Ret => match machine.stack_snapshot().first() {
+            Some(top) => format!("pop return address {} and jump there", top),
+            None => String::from("return with an empty stack"),
+        },
+        Instruction::Out(a) => format!("print {:?}", value(a) as u8 as char),
+        Instruction::In(a) => format!("read a byte of input into {}", Oprnd(a)),
+        Instruction::Noop => String::from("nothing"),
+    }
+}
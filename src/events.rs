@@ -0,0 +1,87 @@
+use std::io::Write;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventKind {
+    In,
+    Out,
+}
+
+impl EventKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            EventKind::In => "in",
+            EventKind::Out => "out",
+        }
+    }
+}
+
+/// One line of an `EventLog`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Event {
+    pub ip: u16,
+    pub kind: EventKind,
+    pub byte: u8,
+}
+
+/// Reads back what `EventLog` wrote. Errors name the line that could not be
+/// parsed.
+pub fn parse(text: &str) -> Result<Vec<Event>, String> {
+    let mut events = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        match parse_event(line.trim()) {
+            Some(event) => events.push(event),
+            None => return Err(format!("line {}: not an event: {}", number + 1, line)),
+        }
+    }
+    Ok(events)
+}
+
+fn parse_event(line: &str) -> Option<Event> {
+    let fields = line.strip_prefix('{')?.strip_suffix('}')?;
+    let (mut ip, mut kind, mut byte) = (None, None, None);
+    for field in fields.split(',') {
+        let (key, value) = field.split_once(':')?;
+        match key.trim() {
+            "\"ip\"" => ip = value.trim().parse().ok(),
+            "\"kind\"" => {
+                kind = match value.trim() {
+                    "\"in\"" => Some(EventKind::In),
+                    "\"out\"" => Some(EventKind::Out),
+                    _ => None,
+                }
+            }
+            "\"byte\"" => byte = value.trim().parse().ok(),
+            _ => return None,
+        }
+    }
+    Some(Event {
+        ip: ip?,
+        kind: kind?,
+        byte: byte?,
+    })
+}
+
+/// Writes every byte the program reads or prints, in order, as JSON lines
+/// like `{"ip":1234,"kind":"in","byte":108}`.
+pub struct EventLog {
+    out: Box<dyn Write>,
+}
+
+impl EventLog {
+    pub fn new(out: Box<dyn Write>) -> EventLog {
+        EventLog { out }
+    }
+
+    pub fn record(&mut self, ip: u16, kind: EventKind, byte: u8) {
+        let _ = writeln!(
+            self.out,
+            "{{\"ip\":{},\"kind\":\"{}\",\"byte\":{}}}",
+            ip,
+            kind.name(),
+            byte
+        );
+    }
+}
@@ -0,0 +1,217 @@
+use std::fs;
+use std::io::{self, stdin, Write};
+
+/// Where the `in` instruction gets its bytes from. The machine drains each
+/// chunk a source returns byte by byte, across line boundaries, before asking
+/// for more; an empty chunk means the input is exhausted. `prompt` is
+/// everything the program printed since the previous chunk was requested.
+pub trait InputSource {
+    fn read_input(&mut self, prompt: &str) -> io::Result<Vec<u8>>;
+
+    /// The type of the source, for diagnostics.
+    fn name(&self) -> &'static str {
+        std::any::type_name::<Self>()
+    }
+}
+
+pub struct StdinLines;
+
+impl InputSource for StdinLines {
+    fn read_input(&mut self, _prompt: &str) -> io::Result<Vec<u8>> {
+        let mut input = String::new();
+        stdin().read_line(&mut input)?;
+        Ok(input.into_bytes())
+    }
+}
+
+#[cfg(feature = "line-editor")]
+pub struct Editor {
+    editor: rustyline::DefaultEditor,
+    history: std::path::PathBuf,
+}
+
+#[cfg(feature = "line-editor")]
+impl Editor {
+    pub fn new(history: std::path::PathBuf) -> Result<Editor, rustyline::error::ReadlineError> {
+        let mut editor = rustyline::DefaultEditor::new()?;
+        // A missing history file just means this is the first session.
+        let _ = editor.load_history(&history);
+        Ok(Editor { editor, history })
+    }
+}
+
+#[cfg(feature = "line-editor")]
+impl InputSource for Editor {
+    fn read_input(&mut self, _prompt: &str) -> io::Result<Vec<u8>> {
+        use rustyline::error::ReadlineError;
+
+        match self.editor.readline("") {
+            Ok(line) => {
+                let _ = self.editor.add_history_entry(line.as_str());
+                let _ = self.editor.save_history(&self.history);
+                Ok((line + "\n").into_bytes())
+            }
+            // Ctrl-D and Ctrl-C end the input like the end of a piped
+            // file: an empty chunk, on which the machine stops cleanly.
+            Err(ReadlineError::Eof) | Err(ReadlineError::Interrupted) => Ok(Vec::new()),
+            Err(ReadlineError::Io(err)) => Err(err),
+            Err(err) => Err(io::Error::other(err)),
+        }
+    }
+}
+
+#[cfg(feature = "line-editor")]
+fn history_path() -> std::path::PathBuf {
+    let home = std::env::var_os("HOME").unwrap_or_else(|| ".".into());
+    std::path::Path::new(&home).join(".synacor_history")
+}
+
+/// The line editor when built with `line-editor` and stdin is a terminal,
+/// plain stdin otherwise so piped input bypasses it entirely.
+pub fn default_source() -> Box<dyn InputSource> {
+    #[cfg(feature = "line-editor")]
+    {
+        use std::io::IsTerminal;
+
+        if stdin().is_terminal() {
+            if let Ok(editor) = Editor::new(history_path()) {
+                return Box::new(editor);
+            }
+        }
+    }
+    Box::new(StdinLines)
+}
+
+/// Answers prompts from a table of `pattern => response` lines, falling back
+/// to another source for anything it does not recognise. A rule matches when
+/// the output since the previous input ends with its pattern, trailing
+/// whitespace ignored; if several match, the one listed first in the file
+/// wins. Each response is written to `echo` as if it had been typed.
+pub struct Autoplay {
+    rules: Vec<(String, String)>,
+    fallback: Box<dyn InputSource>,
+    echo: Box<dyn Write>,
+}
+
+impl Autoplay {
+    pub fn from_file(
+        path: &str,
+        fallback: Box<dyn InputSource>,
+        echo: Box<dyn Write>,
+    ) -> io::Result<Autoplay> {
+        let mut rules = Vec::new();
+        for (number, line) in fs::read_to_string(path)?.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match line.split_once("=>") {
+                Some((pattern, response)) if !pattern.trim().is_empty() => {
+                    rules.push((pattern.trim().to_string(), response.trim().to_string()))
+                }
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("{}:{}: expected `prompt => response`", path, number + 1),
+                    ))
+                }
+            }
+        }
+        Ok(Autoplay {
+            rules,
+            fallback,
+            echo,
+        })
+    }
+}
+
+impl InputSource for Autoplay {
+    fn read_input(&mut self, prompt: &str) -> io::Result<Vec<u8>> {
+        let prompt = prompt.trim_end();
+        match self
+            .rules
+            .iter()
+            .find(|(pattern, _)| prompt.ends_with(pattern.as_str()))
+        {
+            Some((_, response)) => {
+                let line = format!("{}\n", response).into_bytes();
+                self.echo.write_all(&line)?;
+                self.echo.flush()?;
+                Ok(line)
+            }
+            None => self.fallback.read_input(prompt),
+        }
+    }
+}
+
+/// Feeds the contents of a file as one chunk, then hands over to another
+/// source. A missing newline after the last command is added so the program
+/// does not sit waiting for the end of that line.
+pub struct Script {
+    bytes: Option<Vec<u8>>,
+    then: Box<dyn InputSource>,
+}
+
+impl Script {
+    pub fn from_file(path: &str, then: Box<dyn InputSource>) -> io::Result<Script> {
+        let mut bytes = fs::read(path)?;
+        if !bytes.is_empty() && !bytes.ends_with(b"\n") {
+            bytes.push(b'\n');
+        }
+        Ok(Script {
+            bytes: Some(bytes),
+            then,
+        })
+    }
+}
+
+impl InputSource for Script {
+    fn read_input(&mut self, prompt: &str) -> io::Result<Vec<u8>> {
+        match self.bytes.take() {
+            Some(bytes) if !bytes.is_empty() => Ok(bytes),
+            _ => self.then.read_input(prompt),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::Capture;
+    use std::{env, process};
+
+    /// Returns `line` once for every chunk asked for.
+    struct Typed(&'static str);
+
+    impl InputSource for Typed {
+        fn read_input(&mut self, _prompt: &str) -> io::Result<Vec<u8>> {
+            Ok(self.0.as_bytes().to_vec())
+        }
+    }
+
+    #[test]
+    fn autoplay_answers_prompts_ending_with_a_pattern() {
+        let path = env::temp_dir().join(format!("synacor-autoplay-{}", process::id()));
+        fs::write(
+            &path,
+            "# doors\nWhat do you do? => look\nnorth => go north\n",
+        )
+        .unwrap();
+        let echo = Capture::new();
+        let autoplay = Autoplay::from_file(
+            path.to_str().unwrap(),
+            Box::new(Typed("typed\n")),
+            Box::new(echo.clone()),
+        );
+        fs::remove_file(&path).unwrap();
+        let mut autoplay = autoplay.unwrap();
+
+        let answer = autoplay.read_input("There is a door to the north.\n\nWhat do you do?\n");
+        assert_eq!(answer.unwrap(), b"look\n");
+        assert_eq!(echo.bytes(), b"look\n");
+
+        let answer = autoplay.read_input("What do you do? The door north is locked.\n");
+        assert_eq!(answer.unwrap(), b"typed\n");
+        assert_eq!(echo.bytes(), b"look\n");
+    }
+}
@@ -0,0 +1,126 @@
+use crate::{Change, Machine, VmError};
+use std::collections::VecDeque;
+
+pub const DEFAULT_DEPTH: usize = 10_000;
+
+/// What one executed instruction changed.
+struct Entry {
+    ip_before: u16,
+    ip_after: u16,
+    changes: Vec<Change>,
+}
+
+/// Records the effect of each instruction stepped through it so execution
+/// can be walked backwards with `undo` and forwards again with `redo`.
+/// Redo replays the recorded changes instead of executing anything, so it
+/// neither reads input nor prints. Only the last `depth` instructions are
+/// kept, and stepping the machine forward through the journal clears
+/// everything that could have been redone.
+pub struct Journal {
+    undo: VecDeque<Entry>,
+    redo: Vec<Entry>,
+    depth: usize,
+}
+
+impl Default for Journal {
+    fn default() -> Journal {
+        Journal::new(DEFAULT_DEPTH)
+    }
+}
+
+impl Journal {
+    pub fn new(depth: usize) -> Journal {
+        Journal {
+            undo: VecDeque::new(),
+            redo: Vec::new(),
+            depth,
+        }
+    }
+
+    /// Executes one instruction like `Machine::step` and records it.
+    pub fn step(&mut self, machine: &mut Machine) -> Result<bool, VmError> {
+        self.redo.clear();
+        let ip_before = machine.ip;
+        machine.record_changes(true);
+        let result = machine.step();
+        let changes = machine.take_changes();
+        machine.record_changes(false);
+
+        if let Ok(true) = result {
+            if self.undo.len() >= self.depth {
+                self.undo.pop_front();
+            }
+            if self.depth > 0 {
+                self.undo.push_back(Entry {
+                    ip_before,
+                    ip_after: machine.ip,
+                    changes,
+                });
+            }
+        }
+        result
+    }
+
+    /// Makes `change` as if an instruction had, so undoing past this point
+    /// takes it back. Clears the redo history like `step`.
+    pub fn patch(&mut self, machine: &mut Machine, change: Change) {
+        self.redo.clear();
+        machine.apply_change(change, true);
+        if self.undo.len() >= self.depth {
+            self.undo.pop_front();
+        }
+        if self.depth > 0 {
+            self.undo.push_back(Entry {
+                ip_before: machine.ip,
+                ip_after: machine.ip,
+                changes: vec![change],
+            });
+        }
+    }
+
+    /// Reverts the most recent instruction. Returns false if there is
+    /// nothing left to undo.
+    pub fn undo(&mut self, machine: &mut Machine) -> bool {
+        match self.undo.pop_back() {
+            Some(entry) => {
+                for &change in entry.changes.iter().rev() {
+                    machine.apply_change(change, false);
+                }
+                machine.ip = entry.ip_before;
+                self.redo.push(entry);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Reapplies the most recently undone instruction. Returns false if
+    /// there is nothing to redo.
+    pub fn redo(&mut self, machine: &mut Machine) -> bool {
+        match self.redo.pop() {
+            Some(entry) => {
+                for &change in &entry.changes {
+                    machine.apply_change(change, true);
+                }
+                machine.ip = entry.ip_after;
+                self.undo.push_back(entry);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Forgets all history, e.g. after the machine state was replaced.
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+    }
+
+    pub fn undo_len(&self) -> usize {
+        self.undo.len()
+    }
+
+    pub fn redo_len(&self) -> usize {
+        self.redo.len()
+    }
+}
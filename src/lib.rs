@@ -0,0 +1,2099 @@
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::collections::{BTreeSet, LinkedList, VecDeque};
+use std::fmt;
+use std::fs;
+use std::io::{self, Cursor, Read, Write};
+use std::panic::{self, AssertUnwindSafe};
+
+pub mod analysis;
+pub mod asm;
+pub mod debugger;
+pub mod events;
+pub mod input;
+pub mod journal;
+pub mod output;
+pub mod preset;
+pub mod puzzles;
+pub mod report;
+pub mod symbols;
+pub mod trace;
+
+use events::{EventKind, EventLog};
+use input::{InputSource, StdinLines};
+use trace::{CallProfile, RegisterCsv, Tracer};
+
+pub const ADDRESS_RANGE: usize = 1 << 15;
+pub const INTEGER_RANGE: usize = 1 << 15;
+pub const MEMORY_SIZE: usize = 1 << 15;
+pub const NUMBER_OF_REGISTERS: usize = 8;
+/// Most output kept as the prompt for `InputSource::read_input`; older
+/// output is discarded, half of this at a time.
+pub const MAX_PROMPT_LEN: usize = 1 << 16;
+/// How many recently executed addresses `Machine::ip_history` keeps.
+pub const IP_HISTORY_LEN: usize = 64;
+
+/// Mnemonics indexed by opcode.
+pub const OPCODE_NAMES: [&str; 22] = [
+    "halt", "set", "push", "pop", "eq", "gt", "jmp", "jt", "jf", "add", "mult", "mod", "and", "or",
+    "not", "rmem", "wmem", "call", "ret", "out", "in", "noop",
+];
+
+/// Operand counts indexed by opcode.
+const OPCODE_ARITIES: [u8; OPCODE_NAMES.len()] = [
+    0, 2, 1, 1, 3, 3, 1, 2, 2, 3, 3, 3, 3, 3, 2, 2, 2, 1, 0, 1, 1, 0,
+];
+
+/// The number of operands following `opcode` in memory, or `None` if it is
+/// not an opcode.
+///
+/// ```
+/// use synacor_challenge::{opcode_arity, opcode_name};
+///
+/// let isa = [
+///     ("halt", 0), ("set", 2), ("push", 1), ("pop", 1), ("eq", 3), ("gt", 3),
+///     ("jmp", 1), ("jt", 2), ("jf", 2), ("add", 3), ("mult", 3), ("mod", 3),
+///     ("and", 3), ("or", 3), ("not", 2), ("rmem", 2), ("wmem", 2), ("call", 1),
+///     ("ret", 0), ("out", 1), ("in", 1), ("noop", 0),
+/// ];
+/// for (opcode, &(name, arity)) in isa.iter().enumerate() {
+///     assert_eq!(opcode_name(opcode as u16), Some(name));
+///     assert_eq!(opcode_arity(opcode as u16), Some(arity));
+/// }
+/// assert_eq!(opcode_name(22), None);
+/// assert_eq!(opcode_arity(22), None);
+/// ```
+pub fn opcode_arity(opcode: u16) -> Option<u8> {
+    OPCODE_ARITIES.get(opcode as usize).copied()
+}
+
+/// The mnemonic of `opcode`, or `None` if it is not an opcode.
+pub fn opcode_name(opcode: u16) -> Option<&'static str> {
+    OPCODE_NAMES.get(opcode as usize).copied()
+}
+
+/// Called with a read-only view of the machine, see `Machine::set_heartbeat`.
+pub type Heartbeat = Box<dyn FnMut(&Machine)>;
+
+/// Called with a read-only view of the machine, see
+/// `Machine::set_input_hook`.
+pub type InputHook = Box<dyn FnMut(&Machine)>;
+
+pub struct Machine {
+    pub memory: Vec<u16>,
+    pub registers: Vec<u16>,
+    stack: LinkedList<u16>,
+    pub ip: u16,
+    pub input: Box<dyn InputSource>,
+    pub output: Box<dyn Write>,
+    pub warn_overflow: bool,
+    /// Warn on stderr when a subroutine returns with more or fewer entries
+    /// on the stack than it was called with. The game does this on
+    /// purpose in places, so this is informational only.
+    pub warn_unbalanced_stack: bool,
+    /// Bit n set logs every change of rN to stderr, with the instruction
+    /// that made it.
+    pub trace_registers: u8,
+    pub tracer: Option<Tracer>,
+    pub event_log: Option<EventLog>,
+    pub register_csv: Option<RegisterCsv>,
+    pub profile: Option<CallProfile>,
+    /// `push` and `call` fail with `VmError::StackOverflow` once the stack
+    /// would grow beyond this many entries. Unlimited by default, as in the
+    /// spec.
+    pub max_stack_depth: Option<usize>,
+    /// Instructions that would write to memory fail with
+    /// `VmError::TooManyWrites` once this many memory writes have been
+    /// made. Register writes do not count. Unlimited by default.
+    pub max_memory_writes: Option<u64>,
+    pub on_illegal_opcode: IllegalOpcodePolicy,
+    /// Enforce every invariant of the arch spec before each instruction,
+    /// failing with the `VmError` naming the one broken. Illegal opcodes
+    /// are errors then, whatever `on_illegal_opcode` says.
+    pub strict: bool,
+    /// Fail with `VmError::NonAsciiOutput` on an `out` above 127 even
+    /// outside strict mode, for output that must be valid text.
+    pub ascii_output: bool,
+    /// Have `in` fail with `VmError::NeedsInput` when nothing was pushed
+    /// with `push_input`, instead of asking `input` and blocking.
+    pub nonblocking_input: bool,
+    instructions_executed: u64,
+    memory_writes: u64,
+    heartbeat: Option<(u64, Heartbeat)>,
+    input_hook: Option<InputHook>,
+    changes: Option<Vec<Change>>,
+    opcode_counts: Option<[u64; OPCODE_NAMES.len()]>,
+    ip_history: VecDeque<u16>,
+    /// Entry address and stack depth of each call not returned from yet,
+    /// kept while `warn_unbalanced_stack` is on.
+    frames: Vec<(u16, usize)>,
+    input_queue: VecDeque<u8>,
+    prompt: Vec<u8>,
+}
+
+/// A single state change made by an instruction, recorded while
+/// `Machine::record_changes` is on. Output is not recorded: bytes already
+/// printed cannot be taken back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Change {
+    Write {
+        address: Address,
+        old: u16,
+        new: u16,
+    },
+    Push(u16),
+    Pop(u16),
+    /// A byte taken from the pending input.
+    Input(u8),
+}
+
+/// What `step` does with a word that is not a valid instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IllegalOpcodePolicy {
+    /// Fail with `VmError::IllegalOpcode`, as the spec requires.
+    Error,
+    /// Treat the word as a one-word no-op and carry on, for probing how far
+    /// a corrupt program gets. Skipped words show up in the trace.
+    SkipWord,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VmError {
+    InternalPanic {
+        ip: u16,
+    },
+    StackOverflow {
+        ip: u16,
+        depth: usize,
+    },
+    /// An `in` whose operand is not a register.
+    BadInputTarget {
+        ip: u16,
+        operand: u16,
+    },
+    IllegalOpcode {
+        ip: u16,
+        word: u16,
+    },
+    /// A valid opcode whose operands would run past the end of memory.
+    TruncatedInstruction {
+        ip: u16,
+    },
+    /// With `nonblocking_input`, an `in` found no pending input. Nothing
+    /// was executed; push some with `push_input` and step again.
+    NeedsInput {
+        ip: u16,
+    },
+    /// An operand that is neither a number nor a register: 32776 or more
+    /// with the usual eight registers.
+    InvalidOperand {
+        ip: u16,
+        operand: u16,
+    },
+    /// An instruction writing its result somewhere other than a register.
+    /// Always checked for `set`, `eq` and `gt`, for the others only in
+    /// strict mode.
+    NonRegisterDestination {
+        ip: u16,
+        operand: u16,
+    },
+    /// Strict mode or `ascii_output`: an `out` of a value outside ASCII.
+    NonAsciiOutput {
+        ip: u16,
+        value: u16,
+    },
+    /// Strict mode: a value read by an instruction that is not a 15-bit
+    /// number.
+    ValueOutOfRange {
+        ip: u16,
+        value: u16,
+    },
+    /// An instruction that would continue past the last address, e.g. a
+    /// `call` in the last two words, whose return address does not exist,
+    /// or that jumps, calls or returns to an address outside memory.
+    ///
+    /// ```
+    /// use synacor_challenge::{Machine, VmError, MEMORY_SIZE};
+    ///
+    /// let mut memory = vec![0; MEMORY_SIZE];
+    /// memory[MEMORY_SIZE - 2] = 17; // call 0
+    /// let mut machine = Machine::new(memory);
+    /// machine.ip = MEMORY_SIZE as u16 - 2;
+    /// assert_eq!(machine.step(), Err(VmError::PcOverflow { ip: machine.ip }));
+    /// assert_eq!(machine.stack_depth(), 0);
+    /// ```
+    PcOverflow {
+        ip: u16,
+    },
+    /// An instruction writing to memory after `max_memory_writes` writes.
+    TooManyWrites {
+        ip: u16,
+        writes: u64,
+    },
+    /// A program of more words than memory holds, found while loading.
+    ProgramTooLarge {
+        words: usize,
+        max: usize,
+    },
+}
+
+impl fmt::Display for VmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VmError::InternalPanic { ip } => write!(f, "internal panic at ip {}", ip),
+            VmError::StackOverflow { ip, depth } => {
+                write!(f, "stack overflow at ip {} (depth {})", ip, depth)
+            }
+            VmError::BadInputTarget { ip, operand } => write!(
+                f,
+                "in at ip {} stores to {}, which is not a register",
+                ip,
+                Oprnd(*operand)
+            ),
+            VmError::IllegalOpcode { ip, word } => {
+                write!(f, "illegal opcode {} at ip {}", word, ip)
+            }
+            VmError::TruncatedInstruction { ip } => {
+                write!(f, "instruction at ip {} runs past the end of memory", ip)
+            }
+            VmError::NeedsInput { ip } => write!(f, "in at ip {} needs input", ip),
+            VmError::InvalidOperand { ip, operand } => {
+                write!(f, "invalid operand {} at ip {}", operand, ip)
+            }
+            VmError::NonRegisterDestination { ip, operand } => write!(
+                f,
+                "instruction at ip {} writes to {}, which is not a register",
+                ip, operand
+            ),
+            VmError::NonAsciiOutput { ip, value } => {
+                write!(f, "out at ip {} prints {}, which is not ASCII", ip, value)
+            }
+            VmError::ValueOutOfRange { ip, value } => {
+                write!(
+                    f,
+                    "instruction at ip {} reads {}, which is not below 32768",
+                    ip, value
+                )
+            }
+            VmError::PcOverflow { ip } => write!(
+                f,
+                "instruction at ip {} continues past the end of memory",
+                ip
+            ),
+            VmError::TooManyWrites { ip, writes } => write!(
+                f,
+                "instruction at ip {} writes to memory after {} memory writes",
+                ip, writes
+            ),
+            VmError::ProgramTooLarge { words, max } => write!(
+                f,
+                "the program has {} words, but memory only holds {}",
+                words, max
+            ),
+        }
+    }
+}
+
+impl VmError {
+    /// Address of the instruction that failed, or `None` for an error
+    /// raised before anything ran.
+    pub fn ip(&self) -> Option<u16> {
+        Some(match *self {
+            VmError::InternalPanic { ip }
+            | VmError::StackOverflow { ip, .. }
+            | VmError::BadInputTarget { ip, .. }
+            | VmError::IllegalOpcode { ip, .. }
+            | VmError::TruncatedInstruction { ip }
+            | VmError::NeedsInput { ip }
+            | VmError::InvalidOperand { ip, .. }
+            | VmError::NonRegisterDestination { ip, .. }
+            | VmError::NonAsciiOutput { ip, .. }
+            | VmError::ValueOutOfRange { ip, .. }
+            | VmError::PcOverflow { ip }
+            | VmError::TooManyWrites { ip, .. } => ip,
+            VmError::ProgramTooLarge { .. } => return None,
+        })
+    }
+}
+
+impl std::error::Error for VmError {}
+
+/// How an instruction uses memory, see `Instruction::memory_access`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryAccess {
+    None,
+    Read,
+    Write,
+}
+
+/// A decoded instruction. Operands are kept as the raw words found in
+/// memory, so they may still refer to registers; use `get_oprnd_value`
+/// to resolve them at execution time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Instruction {
+    Halt,
+    Set(u16, u16),
+    Push(u16),
+    Pop(u16),
+    Eq(u16, u16, u16),
+    Gt(u16, u16, u16),
+    Jmp(u16),
+    Jt(u16, u16),
+    Jf(u16, u16),
+    Add(u16, u16, u16),
+    Mult(u16, u16, u16),
+    Mod(u16, u16, u16),
+    And(u16, u16, u16),
+    Or(u16, u16, u16),
+    Not(u16, u16),
+    Rmem(u16, u16),
+    Wmem(u16, u16),
+    Call(u16),
+    Ret,
+    Out(u16),
+    In(u16),
+    Noop,
+}
+
+impl Instruction {
+    pub fn opcode(&self) -> u16 {
+        match self {
+            Instruction::Halt => 0,
+            Instruction::Set(_, _) => 1,
+            Instruction::Push(_) => 2,
+            Instruction::Pop(_) => 3,
+            Instruction::Eq(_, _, _) => 4,
+            Instruction::Gt(_, _, _) => 5,
+            Instruction::Jmp(_) => 6,
+            Instruction::Jt(_, _) => 7,
+            Instruction::Jf(_, _) => 8,
+            Instruction::Add(_, _, _) => 9,
+            Instruction::Mult(_, _, _) => 10,
+            Instruction::Mod(_, _, _) => 11,
+            Instruction::And(_, _, _) => 12,
+            Instruction::Or(_, _, _) => 13,
+            Instruction::Not(_, _) => 14,
+            Instruction::Rmem(_, _) => 15,
+            Instruction::Wmem(_, _) => 16,
+            Instruction::Call(_) => 17,
+            Instruction::Ret => 18,
+            Instruction::Out(_) => 19,
+            Instruction::In(_) => 20,
+            Instruction::Noop => 21,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        opcode_name(self.opcode()).unwrap()
+    }
+
+    /// What the instruction does, in terms of its operands a, b and c.
+    /// Arithmetic and bitwise results are reduced to 15 bits, as `bin_op`
+    /// does.
+    ///
+    /// ```
+    /// use synacor_challenge::{decode_at, opcode_name};
+    ///
+    /// for opcode in 0..22 {
+    ///     let instr = decode_at(&[opcode, 0, 0, 0], 0).unwrap();
+    ///     assert!(!instr.effect().is_empty(), "{}", opcode_name(opcode).unwrap());
+    /// }
+    /// let or = decode_at(&[13, 32768, 32769, 32770], 0).unwrap();
+    /// assert_eq!(or.effect(), "reg a = b | c (low 15 bits)");
+    /// ```
+    pub fn effect(&self) -> &'static str {
+        match self {
+            Instruction::Halt => "stop execution",
+            Instruction::Set(_, _) => "reg a = b",
+            Instruction::Push(_) => "push a onto the stack",
+            Instruction::Pop(_) => "reg a = value popped off the stack",
+            Instruction::Eq(_, _, _) => "reg a = 1 if b == c, else 0",
+            Instruction::Gt(_, _, _) => "reg a = 1 if b > c, else 0",
+            Instruction::Jmp(_) => "jump to a",
+            Instruction::Jt(_, _) => "jump to b if a is nonzero",
+            Instruction::Jf(_, _) => "jump to b if a is zero",
+            Instruction::Add(_, _, _) => "reg a = b + c (mod 32768)",
+            Instruction::Mult(_, _, _) => "reg a = b * c (mod 32768)",
+            Instruction::Mod(_, _, _) => "reg a = b % c (low 15 bits)",
+            Instruction::And(_, _, _) => "reg a = b & c (low 15 bits)",
+            Instruction::Or(_, _, _) => "reg a = b | c (low 15 bits)",
+            Instruction::Not(_, _) => "reg a = 15-bit inverse of b (!b & 0x7fff)",
+            Instruction::Rmem(_, _) => "reg a = memory at address b",
+            Instruction::Wmem(_, _) => "memory at address a = b",
+            Instruction::Call(_) => "push the address of the next instruction, jump to a",
+            Instruction::Ret => "pop an address off the stack and jump to it",
+            Instruction::Out(_) => "print the character with code a",
+            Instruction::In(_) => "reg a = next input character, reading a line if none is left",
+            Instruction::Noop => "do nothing",
+        }
+    }
+
+    /// The raw operand the instruction writes its result to, if any.
+    pub fn destination(&self) -> Option<u16> {
+        match *self {
+            Instruction::Set(a, _)
+            | Instruction::Pop(a)
+            | Instruction::Eq(a, _, _)
+            | Instruction::Gt(a, _, _)
+            | Instruction::Add(a, _, _)
+            | Instruction::Mult(a, _, _)
+            | Instruction::Mod(a, _, _)
+            | Instruction::And(a, _, _)
+            | Instruction::Or(a, _, _)
+            | Instruction::Not(a, _)
+            | Instruction::Rmem(a, _)
+            | Instruction::In(a) => Some(a),
+            _ => None,
+        }
+    }
+
+    /// Registers whose values the instruction reads, by index. Operands that
+    /// are numbers are left out.
+    ///
+    /// ```
+    /// use synacor_challenge::{asm::assemble, decode_at, MemoryAccess};
+    ///
+    /// let add = decode_at(&assemble("add r0 r1 5").unwrap(), 0).unwrap();
+    /// assert_eq!(add.reads().into_iter().collect::<Vec<_>>(), [1]);
+    /// assert_eq!(add.writes().into_iter().collect::<Vec<_>>(), [0]);
+    /// assert_eq!(add.memory_access(), MemoryAccess::None);
+    ///
+    /// let wmem = decode_at(&assemble("wmem r2 r3").unwrap(), 0).unwrap();
+    /// assert_eq!(wmem.reads().into_iter().collect::<Vec<_>>(), [2, 3]);
+    /// assert!(wmem.writes().is_empty());
+    /// assert_eq!(wmem.memory_access(), MemoryAccess::Write);
+    /// ```
+    pub fn reads(&self) -> BTreeSet<usize> {
+        self.sources()
+            .into_iter()
+            .filter_map(|oprnd| match get_addr(oprnd, NUMBER_OF_REGISTERS) {
+                Some(Address::Reg(reg)) => Some(reg),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// The register the instruction writes, by index, if its destination is
+    /// one.
+    pub fn writes(&self) -> BTreeSet<usize> {
+        match self
+            .destination()
+            .and_then(|oprnd| get_addr(oprnd, NUMBER_OF_REGISTERS))
+        {
+            Some(Address::Reg(reg)) => BTreeSet::from([reg]),
+            _ => BTreeSet::new(),
+        }
+    }
+
+    /// Whether the instruction reads or writes memory through an operand.
+    /// The stack and the instruction's own words do not count.
+    pub fn memory_access(&self) -> MemoryAccess {
+        match *self {
+            Instruction::Rmem(_, _) => MemoryAccess::Read,
+            Instruction::Wmem(_, _) => MemoryAccess::Write,
+            _ => match self
+                .destination()
+                .and_then(|oprnd| get_addr(oprnd, NUMBER_OF_REGISTERS))
+            {
+                Some(Address::Mem(_)) => MemoryAccess::Write,
+                _ => MemoryAccess::None,
+            },
+        }
+    }
+
+    /// The raw operands whose values the instruction reads.
+    pub fn sources(&self) -> Vec<u16> {
+        let mut operands = self.operands();
+        if self.destination().is_some() {
+            operands.remove(0);
+        }
+        operands
+    }
+
+    pub fn operands(&self) -> Vec<u16> {
+        match *self {
+            Instruction::Halt | Instruction::Ret | Instruction::Noop => vec![],
+            Instruction::Push(a)
+            | Instruction::Pop(a)
+            | Instruction::Jmp(a)
+            | Instruction::Call(a)
+            | Instruction::Out(a)
+            | Instruction::In(a) => vec![a],
+            Instruction::Set(a, b)
+            | Instruction::Jt(a, b)
+            | Instruction::Jf(a, b)
+            | Instruction::Not(a, b)
+            | Instruction::Rmem(a, b)
+            | Instruction::Wmem(a, b) => vec![a, b],
+            Instruction::Eq(a, b, c)
+            | Instruction::Gt(a, b, c)
+            | Instruction::Add(a, b, c)
+            | Instruction::Mult(a, b, c)
+            | Instruction::Mod(a, b, c)
+            | Instruction::And(a, b, c)
+            | Instruction::Or(a, b, c) => vec![a, b, c],
+        }
+    }
+
+    /// Number of memory words the instruction occupies, opcode included.
+    pub fn size(&self) -> u16 {
+        opcode_arity(self.opcode()).unwrap() as u16 + 1
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())?;
+        for oprnd in self.operands() {
+            write!(f, " {}", Oprnd(oprnd))?;
+        }
+        Ok(())
+    }
+}
+
+/// Formats a raw operand word symbolically: literals as numbers, register
+/// references as `r0`..`r7`.
+pub struct Oprnd(pub u16);
+
+impl fmt::Display for Oprnd {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match get_addr(self.0, NUMBER_OF_REGISTERS) {
+            Some(Address::Reg(reg)) => write!(f, "r{}", reg),
+            Some(Address::Mem(_)) => write!(f, "{}", self.0),
+            None => write!(f, "<invalid {}>", self.0),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Address {
+    Mem(usize),
+    Reg(usize),
+}
+
+pub fn read_mem(mach: &Machine, address: Address) -> u16 {
+    match address {
+        Address::Mem(addr) => mach.memory[addr],
+        Address::Reg(addr) => mach.registers[addr],
+    }
+}
+
+pub fn write_mem(mach: &mut Machine, address: Address, value: u16) {
+    let old = read_mem(mach, address);
+    mach.record(Change::Write {
+        address,
+        old,
+        new: value,
+    });
+    match address {
+        Address::Mem(addr) => {
+            mach.memory_writes += 1;
+            mach.memory[addr] = value;
+        }
+        Address::Reg(addr) => {
+            // Only the first eight registers can be traced.
+            let traced = mach.trace_registers.checked_shr(addr as u32).unwrap_or(0) & 1 != 0;
+            if traced && old != value {
+                let instr = get_op(mach).map_or_else(String::new, |instr| instr.to_string());
+                eprintln!("{:5}: r{} {} -> {} ({})", mach.ip, addr, old, value, instr);
+            }
+            mach.registers[addr] = value;
+        }
+    }
+}
+
+pub fn get_oprnd_value(mach: &Machine, oprnd: u16) -> u16 {
+    if (oprnd as usize) < INTEGER_RANGE {
+        oprnd
+    } else {
+        read_mem(mach, mach.address(oprnd).unwrap())
+    }
+}
+
+/// Decodes the instruction starting at `addr` without touching any machine
+/// state. Returns `None` for unknown opcodes or instructions running past
+/// the end of `memory`.
+pub fn decode_at(memory: &[u16], addr: usize) -> Option<Instruction> {
+    let arg = |offset: usize| memory.get(addr + offset).copied();
+    match *memory.get(addr)? {
+        0 => Some(Instruction::Halt),
+        1 => Some(Instruction::Set(arg(1)?, arg(2)?)),
+        2 => Some(Instruction::Push(arg(1)?)),
+        3 => Some(Instruction::Pop(arg(1)?)),
+        4 => Some(Instruction::Eq(arg(1)?, arg(2)?, arg(3)?)),
+        5 => Some(Instruction::Gt(arg(1)?, arg(2)?, arg(3)?)),
+        6 => Some(Instruction::Jmp(arg(1)?)),
+        7 => Some(Instruction::Jt(arg(1)?, arg(2)?)),
+        8 => Some(Instruction::Jf(arg(1)?, arg(2)?)),
+        9 => Some(Instruction::Add(arg(1)?, arg(2)?, arg(3)?)),
+        10 => Some(Instruction::Mult(arg(1)?, arg(2)?, arg(3)?)),
+        11 => Some(Instruction::Mod(arg(1)?, arg(2)?, arg(3)?)),
+        12 => Some(Instruction::And(arg(1)?, arg(2)?, arg(3)?)),
+        13 => Some(Instruction::Or(arg(1)?, arg(2)?, arg(3)?)),
+        14 => Some(Instruction::Not(arg(1)?, arg(2)?)),
+        15 => Some(Instruction::Rmem(arg(1)?, arg(2)?)),
+        16 => Some(Instruction::Wmem(arg(1)?, arg(2)?)),
+        17 => Some(Instruction::Call(arg(1)?)),
+        18 => Some(Instruction::Ret),
+        19 => Some(Instruction::Out(arg(1)?)),
+        20 => Some(Instruction::In(arg(1)?)),
+        21 => Some(Instruction::Noop),
+        _ => None,
+    }
+}
+
+/// Parses a number given in decimal or as `0x` hex.
+pub fn parse_number(text: &str) -> Option<usize> {
+    match text.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+/// Decodes a program image into a fresh memory, failing with
+/// `VmError::ProgramTooLarge` if it has more words than memory holds.
+///
+/// ```
+/// use synacor_challenge::{load_memory, VmError, MEMORY_SIZE};
+///
+/// assert_eq!(load_memory(vec![9, 0, 1, 0]).unwrap()[..3], [9, 1, 0]);
+/// assert_eq!(
+///     load_memory(vec![0; (MEMORY_SIZE + 1) * 2]),
+///     Err(VmError::ProgramTooLarge {
+///         words: MEMORY_SIZE + 1,
+///         max: MEMORY_SIZE
+///     })
+/// );
+/// ```
+pub fn load_memory(file: Vec<u8>) -> Result<Vec<u16>, VmError> {
+    let file_size = file.len() / 2;
+    if file_size > MEMORY_SIZE {
+        return Err(VmError::ProgramTooLarge {
+            words: file_size,
+            max: MEMORY_SIZE,
+        });
+    }
+    let mut buffer: [u16; MEMORY_SIZE] = [0; MEMORY_SIZE];
+    let mut rdr: Cursor<Vec<u8>> = Cursor::new(file);
+    rdr.read_u16_into::<LittleEndian>(&mut buffer[0..file_size])
+        .unwrap();
+
+    Ok(buffer.to_vec())
+}
+
+/// Programs shorter than this many words are most likely the wrong file.
+pub const MIN_PLAUSIBLE_WORDS: usize = 8;
+
+/// Checks that a program of `len` bytes can be loaded: not empty, a whole
+/// number of words and no larger than memory.
+pub fn check_program_size(len: usize) -> io::Result<()> {
+    let problem = if len == 0 {
+        String::from("the program is empty")
+    } else if !len.is_multiple_of(2) {
+        format!(
+            "the program has an odd length of {} bytes, but words take two",
+            len
+        )
+    } else if len > MEMORY_SIZE * 2 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            VmError::ProgramTooLarge {
+                words: len / 2,
+                max: MEMORY_SIZE,
+            },
+        ));
+    } else {
+        return Ok(());
+    };
+    Err(io::Error::new(io::ErrorKind::InvalidData, problem))
+}
+
+/// Reads a program file into a fresh memory image, failing as
+/// `check_program_size` does and warning on stderr about a program shorter
+/// than `MIN_PLAUSIBLE_WORDS`. With the `mmap` feature the file is
+/// memory-mapped and decoded straight from the mapping, falling back to
+/// reading it normally if mapping fails.
+pub fn load_file(path: &str) -> io::Result<Vec<u16>> {
+    let len = fs::metadata(path)?.len() as usize;
+    check_program_size(len)?;
+    if len / 2 < MIN_PLAUSIBLE_WORDS {
+        eprintln!(
+            "warning: {} has only {} words, is it the right file?",
+            path,
+            len / 2
+        );
+    }
+    #[cfg(feature = "mmap")]
+    {
+        if let Ok(memory) = load_mapped(path) {
+            return Ok(memory);
+        }
+    }
+    let bytes = fs::read(path)?;
+    check_program_size(bytes.len())?;
+    load_memory(bytes).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+/// Like `load_file`, but a program larger than memory is cut off at the end
+/// of memory with a warning on stderr instead of failing.
+pub fn load_file_truncated(path: &str) -> io::Result<Vec<u16>> {
+    let mut bytes = fs::read(path)?;
+    if bytes.len() > MEMORY_SIZE * 2 {
+        eprintln!(
+            "warning: {} has {} words, loading only the first {}",
+            path,
+            bytes.len() / 2,
+            MEMORY_SIZE
+        );
+        bytes.truncate(MEMORY_SIZE * 2);
+    }
+    check_program_size(bytes.len())?;
+    load_memory(bytes).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+/// Builds a memory image from several program files, each placed at its
+/// own offset, e.g. a main routine at 0 and a library at 1000. Fails if a
+/// segment does not fit in memory or overlaps an earlier one.
+pub fn load_segments(segments: &[(&str, usize)]) -> io::Result<Vec<u16>> {
+    let mut memory = vec![0; MEMORY_SIZE];
+    let mut placed: Vec<(&str, usize, usize)> = Vec::new();
+    for &(path, offset) in segments {
+        let with_path = |err: io::Error| io::Error::new(err.kind(), format!("{}: {}", path, err));
+        let bytes = fs::read(path).map_err(with_path)?;
+        check_program_size(bytes.len()).map_err(with_path)?;
+        let end = offset + bytes.len() / 2;
+        let fail = |problem: String| {
+            Err(with_path(io::Error::new(
+                io::ErrorKind::InvalidData,
+                problem,
+            )))
+        };
+        if end > MEMORY_SIZE {
+            return fail(format!(
+                "{} words at {} run past the end of memory",
+                bytes.len() / 2,
+                offset
+            ));
+        }
+        if let Some((other, start, _)) = placed
+            .iter()
+            .find(|&&(_, start, other_end)| offset < other_end && start < end)
+        {
+            return fail(format!("overlaps {} loaded at {}", other, start));
+        }
+        let mut words = Cursor::new(bytes);
+        words.read_u16_into::<LittleEndian>(&mut memory[offset..end])?;
+        placed.push((path, offset, end));
+    }
+    Ok(memory)
+}
+
+#[cfg(feature = "mmap")]
+fn load_mapped(path: &str) -> io::Result<Vec<u16>> {
+    use byteorder::ByteOrder;
+
+    let file = fs::File::open(path)?;
+    // The mapping is read-only and does not outlive this function; the file
+    // changing underneath it while loading is no different from a racing
+    // write during a normal read.
+    let map = unsafe { memmap2::Mmap::map(&file)? };
+    check_program_size(map.len())?;
+
+    let file_size = map.len() / 2;
+    let mut buffer: Vec<u16> = vec![0; MEMORY_SIZE];
+    LittleEndian::read_u16_into(&map[..file_size * 2], &mut buffer[..file_size]);
+    Ok(buffer)
+}
+
+/// Writes `memory` in the same little-endian layout `load_memory` reads, so
+/// a dump can be loaded again as a program.
+pub fn dump_memory<W: Write>(memory: &[u16], out: &mut W) -> io::Result<()> {
+    for &word in memory {
+        out.write_u16::<LittleEndian>(word)?;
+    }
+    out.flush()
+}
+
+pub fn get_op(mach: &Machine) -> Option<Instruction> {
+    decode_at(&mach.memory, mach.ip as usize)
+}
+
+/// Where operand `addr` refers to on a machine with `registers` registers;
+/// `Machine::address` does this for a given machine. Code that has no
+/// machine at hand passes the spec's `NUMBER_OF_REGISTERS`.
+pub fn get_addr(addr: u16, registers: usize) -> Option<Address> {
+    if (addr as usize) < ADDRESS_RANGE {
+        Some(Address::Mem(addr as usize))
+    } else if (addr as usize) < ADDRESS_RANGE + registers {
+        Some(Address::Reg((addr as usize) - ADDRESS_RANGE))
+    } else {
+        None
+    }
+}
+
+/// Executes `eq` or `gt`, setting the destination, which must be a
+/// register, to 1 if the comparison holds and 0 otherwise.
+///
+/// ```
+/// use synacor_challenge::{asm::assemble, Machine};
+///
+/// for (program, expected) in [
+///     ("eq r0 5 5", 1),
+///     ("eq r0 5 6", 0),
+///     ("gt r0 6 5", 1),
+///     ("gt r0 5 5", 0),
+/// ] {
+///     let words = assemble(&format!("set r0 7\n{}\nhalt", program)).unwrap();
+///     let mut machine = Machine::from_words(&words).unwrap();
+///     machine.run().unwrap();
+///     assert_eq!(machine.registers[0], expected, "{}", program);
+/// }
+///
+/// let words = assemble("eq 100 5 5\nhalt").unwrap();
+/// assert!(Machine::from_words(&words).unwrap().run().is_err());
+/// ```
+pub fn comp_op(mach: &mut Machine, instr: Instruction) -> Result<(), VmError> {
+    let (a, holds) = match instr {
+        Instruction::Eq(a, b, c) => (a, get_oprnd_value(mach, b) == get_oprnd_value(mach, c)),
+        Instruction::Gt(a, b, c) => (a, get_oprnd_value(mach, b) > get_oprnd_value(mach, c)),
+        _ => return Ok(()),
+    };
+    let addr = match mach.address(a) {
+        Some(addr @ Address::Reg(_)) => addr,
+        _ => {
+            return Err(VmError::NonRegisterDestination {
+                ip: mach.ip,
+                operand: a,
+            })
+        }
+    };
+    write_mem(mach, addr, holds as u16);
+    Ok(())
+}
+
+/// Executes `add`, `mult`, `mod`, `and` or `or`. Every result is reduced
+/// to 15 bits, even for operands that are not, such as a word `rmem` read
+/// from data.
+///
+/// There is no subtraction; programs compute `a - b` as `a + (~b + 1)`,
+/// since `~b + 1` is `32768 - b` modulo 32768. Both additions wrap, so with
+/// `warn_overflow` this idiom reports an overflow whenever `a >= b`.
+///
+/// ```
+/// use synacor_challenge::{asm::assemble, Machine};
+///
+/// let program = "set r0 1000\nset r1 1234\nnot r2 r1\nadd r2 r2 1\nadd r3 r0 r2\nhalt";
+/// let mut machine = Machine::from_words(&assemble(program).unwrap()).unwrap();
+/// machine.run().unwrap();
+/// assert_eq!(machine.registers[3], 32768 + 1000 - 1234);
+/// ```
+pub fn bin_op(mach: &mut Machine, op: fn(usize, usize) -> usize, instr: Instruction) {
+    let (addr, result) = match instr {
+        Instruction::Add(a, b, c) | Instruction::Mult(a, b, c) => {
+            let (b, c) = (get_oprnd_value(mach, b), get_oprnd_value(mach, c));
+            let result = op(b as usize, c as usize);
+            if mach.warn_overflow && result >= INTEGER_RANGE {
+                let symbol = if let Instruction::Add(_, _, _) = instr {
+                    '+'
+                } else {
+                    '*'
+                };
+                eprintln!(
+                    "overflow at ip {}: {} ({} {} {} = {})",
+                    mach.ip, instr, b, symbol, c, result
+                );
+            }
+            (mach.address(a).unwrap(), result % INTEGER_RANGE)
+        }
+        Instruction::Mod(a, b, c) | Instruction::And(a, b, c) | Instruction::Or(a, b, c) => {
+            let (b, c) = (get_oprnd_value(mach, b), get_oprnd_value(mach, c));
+            (
+                mach.address(a).unwrap(),
+                op(b as usize, c as usize) % INTEGER_RANGE,
+            )
+        }
+        _ => return,
+    };
+    write_mem(mach, addr, result as u16);
+}
+
+/// Why `Machine::run_until_input` returned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SegmentEnd {
+    /// The next instruction is an `in` with no input pending.
+    Input,
+    Halted,
+}
+
+/// What the machine printed between two stops.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RunSegment {
+    pub output: String,
+    pub end: SegmentEnd,
+}
+
+/// Deep-copies the machine state. The input source, output sink, tracer,
+/// event log, register CSV, profile, heartbeat and input hook are not
+/// cloned and change recording is off: a clone reads from stdin, writes to
+/// stdout and has no hooks until it is given its own.
+impl Clone for Machine {
+    fn clone(&self) -> Machine {
+        Machine {
+            memory: self.memory.clone(),
+            registers: self.registers.clone(),
+            stack: self.stack.clone(),
+            ip: self.ip,
+            input: Box::new(StdinLines),
+            output: Box::new(io::stdout()),
+            warn_overflow: self.warn_overflow,
+            warn_unbalanced_stack: self.warn_unbalanced_stack,
+            trace_registers: self.trace_registers,
+            tracer: None,
+            event_log: None,
+            register_csv: None,
+            profile: None,
+            max_stack_depth: self.max_stack_depth,
+            max_memory_writes: self.max_memory_writes,
+            on_illegal_opcode: self.on_illegal_opcode,
+            strict: self.strict,
+            ascii_output: self.ascii_output,
+            nonblocking_input: self.nonblocking_input,
+            instructions_executed: self.instructions_executed,
+            memory_writes: self.memory_writes,
+            heartbeat: None,
+            input_hook: None,
+            changes: None,
+            opcode_counts: self.opcode_counts,
+            ip_history: self.ip_history.clone(),
+            frames: self.frames.clone(),
+            input_queue: self.input_queue.clone(),
+            prompt: self.prompt.clone(),
+        }
+    }
+}
+
+impl Machine {
+    pub fn instructions_executed(&self) -> u64 {
+        self.instructions_executed
+    }
+
+    /// Calls `callback` after every `interval` executed instructions, e.g. to
+    /// report progress or sample state during long runs. The callback only
+    /// gets a read-only view of the machine. An interval of zero removes the
+    /// heartbeat.
+    pub fn set_heartbeat(&mut self, interval: u64, callback: Heartbeat) {
+        self.heartbeat = if interval == 0 {
+            None
+        } else {
+            Some((interval, callback))
+        };
+    }
+
+    /// Calls `callback` whenever an `in` is about to ask `input` for a line,
+    /// with ip still at the `in`, e.g. to autosave at every prompt.
+    pub fn set_input_hook(&mut self, callback: InputHook) {
+        self.input_hook = Some(callback);
+    }
+
+    /// Turns recording of state changes on or off. Turning it off discards
+    /// anything not yet collected with `take_changes`.
+    pub fn record_changes(&mut self, on: bool) {
+        self.changes = if on { Some(Vec::new()) } else { None };
+    }
+
+    /// The changes recorded since the previous call, oldest first.
+    pub fn take_changes(&mut self) -> Vec<Change> {
+        match &mut self.changes {
+            Some(changes) => std::mem::take(changes),
+            None => Vec::new(),
+        }
+    }
+
+    /// Turns counting of executed instructions per opcode on, from zero, or
+    /// off.
+    pub fn count_opcodes(&mut self, on: bool) {
+        self.opcode_counts = if on {
+            Some([0; OPCODE_NAMES.len()])
+        } else {
+            None
+        };
+    }
+
+    /// Executed instructions per opcode, indexed like `OPCODE_NAMES`, while
+    /// `count_opcodes` is on.
+    pub fn opcode_counts(&self) -> Option<&[u64; OPCODE_NAMES.len()]> {
+        self.opcode_counts.as_ref()
+    }
+
+    /// Applies `change` again, or reverses it if `forward` is false. Neither
+    /// direction is recorded or shows up in the register log.
+    pub fn apply_change(&mut self, change: Change, forward: bool) {
+        let recording = self.changes.take();
+        let trace_registers = std::mem::take(&mut self.trace_registers);
+        match (change, forward) {
+            (Change::Write { address, new, .. }, true) => write_mem(self, address, new),
+            (Change::Write { address, old, .. }, false) => write_mem(self, address, old),
+            (Change::Push(value), true) | (Change::Pop(value), false) => {
+                self.stack.push_front(value)
+            }
+            (Change::Push(_), false) | (Change::Pop(_), true) => {
+                self.stack.pop_front();
+            }
+            (Change::Input(_), true) => {
+                self.input_queue.pop_front();
+            }
+            (Change::Input(byte), false) => self.input_queue.push_front(byte),
+        }
+        self.changes = recording;
+        self.trace_registers = trace_registers;
+    }
+
+    fn record(&mut self, change: Change) {
+        if let Some(changes) = &mut self.changes {
+            changes.push(change);
+        }
+    }
+
+    /// Whether both machines have the same memory, registers, stack and ip,
+    /// the state `state_hash` covers. Input, output, hooks and counters are
+    /// not compared.
+    ///
+    /// ```
+    /// use synacor_challenge::{asm::assemble, Machine};
+    ///
+    /// let words = assemble("push 1\nhalt").unwrap();
+    /// let mut machine = Machine::from_words(&words).unwrap();
+    /// assert!(machine.state_eq(&Machine::from_words(&words).unwrap()));
+    /// machine.step().unwrap();
+    /// assert!(!machine.state_eq(&Machine::from_words(&words).unwrap()));
+    /// ```
+    pub fn state_eq(&self, other: &Machine) -> bool {
+        self.ip == other.ip
+            && self.registers == other.registers
+            && self.stack == other.stack
+            && self.memory == other.memory
+    }
+
+    /// A fingerprint of memory, registers, stack and ip, for telling at a
+    /// glance whether two runs reached the same state. Because ip and the
+    /// stack are included, identical memory reached with different control
+    /// state hashes differently. Input, output and counters are left out.
+    pub fn state_hash(&self) -> u64 {
+        // The FxHash mixing step: fast, and plenty for comparing states.
+        fn mix(hash: u64, word: u16) -> u64 {
+            (hash.rotate_left(5) ^ word as u64).wrapping_mul(0x517c_c1b7_2722_0a95)
+        }
+        let mut hash = mix(0, self.ip);
+        for &word in self.registers.iter().chain(&self.memory) {
+            hash = mix(hash, word);
+        }
+        hash = mix(hash, self.stack.len() as u16);
+        for &word in &self.stack {
+            hash = mix(hash, word);
+        }
+        hash
+    }
+
+    /// The program in memory as instructions with their addresses, in
+    /// address order. With `reachable`, only what control flow reaches
+    /// statically from address 0, as `analysis::reachable_code` finds it.
+    /// Otherwise every word is decoded from 0 in a linear sweep, failing at
+    /// the first that does not start a whole instruction, so this suits
+    /// programs without data mixed into the code.
+    ///
+    /// ```
+    /// use synacor_challenge::{asm::assemble, Machine};
+    ///
+    /// let source = "set r0 1\nadd r0 r0 2\nout r0\nhalt";
+    /// let words = assemble(source).unwrap();
+    /// let machine = Machine::from_words(&words).unwrap();
+    /// let decoded = machine.decode_program(true).unwrap();
+    /// let addresses: Vec<u16> = decoded.iter().map(|&(addr, _)| addr).collect();
+    /// assert_eq!(addresses, [0, 3, 7, 9]);
+    /// let text: Vec<String> = decoded.iter().map(|(_, instr)| instr.to_string()).collect();
+    /// assert_eq!(assemble(&text.join("\n")).unwrap(), words);
+    /// assert_eq!(machine.decode_program(false).unwrap()[..4], decoded[..]);
+    /// ```
+    pub fn decode_program(&self, reachable: bool) -> Result<Vec<(u16, Instruction)>, VmError> {
+        if reachable {
+            return Ok(analysis::reachable_code(&self.memory).into_iter().collect());
+        }
+        let mut program = Vec::new();
+        let mut addr = 0;
+        while addr < self.memory.len() {
+            let ip = addr as u16;
+            match decode_at(&self.memory, addr) {
+                Some(instr) => {
+                    program.push((ip, instr));
+                    addr += instr.size() as usize;
+                }
+                None if opcode_arity(self.memory[addr]).is_some() => {
+                    return Err(VmError::TruncatedInstruction { ip })
+                }
+                None => {
+                    return Err(VmError::IllegalOpcode {
+                        ip,
+                        word: self.memory[addr],
+                    })
+                }
+            }
+        }
+        Ok(program)
+    }
+
+    /// Writes to memory made so far, by any instruction.
+    pub fn memory_writes(&self) -> u64 {
+        self.memory_writes
+    }
+
+    /// Addresses of the last `IP_HISTORY_LEN` instructions stepped, oldest
+    /// first, including one that failed.
+    pub fn ip_history(&self) -> Vec<u16> {
+        self.ip_history.iter().copied().collect()
+    }
+
+    pub fn stack_depth(&self) -> usize {
+        self.stack.len()
+    }
+
+    /// The stack from top to bottom.
+    pub fn stack_snapshot(&self) -> Vec<u16> {
+        self.stack.iter().copied().collect()
+    }
+
+    /// Pushes `values` in order, so the last ends up on top, e.g. a return
+    /// address for starting inside a subroutine.
+    ///
+    /// ```
+    /// use synacor_challenge::{asm::assemble, Machine};
+    ///
+    /// let words = assemble("ret\nset r0 9\nhalt").unwrap();
+    /// let mut machine = Machine::from_words(&words).unwrap();
+    /// machine.seed_stack(&[5, 1]);
+    /// machine.run().unwrap();
+    /// assert_eq!(machine.registers[0], 9);
+    /// assert_eq!(machine.stack_snapshot(), vec![5]);
+    /// ```
+    pub fn seed_stack(&mut self, values: &[u16]) {
+        for &value in values {
+            self.stack.push_front(value);
+        }
+    }
+
+    /// Overwrites the entry `index` places below the top of the stack and
+    /// returns what was there, or `None` if the stack is not that deep.
+    pub fn set_stack_entry(&mut self, index: usize, value: u16) -> Option<u16> {
+        let entry = self.stack.iter_mut().nth(index)?;
+        Some(std::mem::replace(entry, value))
+    }
+
+    /// Whether the output since the last input ends with `pattern`.
+    pub fn output_ends_with(&self, pattern: &str) -> bool {
+        self.prompt.ends_with(pattern.as_bytes())
+    }
+
+    /// Everything printed since the program last read input.
+    pub fn output_since_input(&self) -> String {
+        String::from_utf8_lossy(&self.prompt).into_owned()
+    }
+
+    /// Queues `bytes` for `in` to read before anything from `input`, e.g. a
+    /// line typed into a frontend, newline included.
+    pub fn push_input(&mut self, bytes: &[u8]) {
+        self.prompt.clear();
+        self.input_queue.extend(bytes);
+    }
+
+    /// Whether the next instruction is an `in` with no input queued, so
+    /// stepping would block on `input` or, with `nonblocking_input`, fail
+    /// with `VmError::NeedsInput`.
+    pub fn needs_input(&self) -> bool {
+        matches!(get_op(self), Some(Instruction::In(_))) && self.input_queue.is_empty()
+    }
+
+    /// Starts over on `memory` as if freshly created: ip 0, registers
+    /// zeroed, stack and pending input empty. Settings and hooks are kept.
+    pub fn reset(&mut self, memory: Vec<u16>) {
+        self.memory = memory;
+        self.registers.iter_mut().for_each(|value| *value = 0);
+        self.stack.clear();
+        self.ip = 0;
+        self.instructions_executed = 0;
+        self.memory_writes = 0;
+        self.ip_history.clear();
+        self.frames.clear();
+        self.input_queue.clear();
+        self.prompt.clear();
+    }
+
+    pub fn new(memory: Vec<u16>) -> Machine {
+        Machine {
+            memory,
+            registers: vec![0u16; NUMBER_OF_REGISTERS],
+            stack: LinkedList::new(),
+            ip: 0u16,
+            input: Box::new(StdinLines),
+            output: Box::new(io::stdout()),
+            warn_overflow: false,
+            warn_unbalanced_stack: false,
+            trace_registers: 0,
+            tracer: None,
+            event_log: None,
+            register_csv: None,
+            profile: None,
+            max_stack_depth: None,
+            max_memory_writes: None,
+            on_illegal_opcode: IllegalOpcodePolicy::Error,
+            strict: false,
+            ascii_output: false,
+            nonblocking_input: false,
+            instructions_executed: 0,
+            memory_writes: 0,
+            heartbeat: None,
+            input_hook: None,
+            changes: None,
+            opcode_counts: None,
+            ip_history: VecDeque::with_capacity(IP_HISTORY_LEN),
+            frames: Vec::new(),
+            input_queue: VecDeque::new(),
+            prompt: Vec::new(),
+        }
+    }
+
+    /// Where operand `oprnd` refers to: memory below 32768, then one
+    /// address per register, so by default the last valid operand is 32775.
+    ///
+    /// ```
+    /// use synacor_challenge::{Address, Machine};
+    ///
+    /// let mut machine = Machine::from_words(&[]).unwrap();
+    /// assert_eq!(machine.address(32775), Some(Address::Reg(7)));
+    /// assert_eq!(machine.address(32776), None);
+    /// machine.set_register_count(10);
+    /// assert_eq!(machine.address(32776), Some(Address::Reg(8)));
+    /// ```
+    pub fn address(&self, oprnd: u16) -> Option<Address> {
+        get_addr(oprnd, self.registers.len())
+    }
+
+    /// Changes the number of registers from the spec's
+    /// `NUMBER_OF_REGISTERS`, for experimenting with ISA variants. New
+    /// registers start at zero. Operands past the last register fail with
+    /// `VmError::InvalidOperand`. Disassembly and traces still only know
+    /// eight registers, and saved CPU states only load into a machine with
+    /// the same count.
+    ///
+    /// Panics if `count` leaves no room for it in a 16-bit operand.
+    pub fn set_register_count(&mut self, count: usize) {
+        assert!(
+            ADDRESS_RANGE + count <= 1 << 16,
+            "{} registers do not fit in 16-bit operands",
+            count
+        );
+        self.registers.resize(count, 0);
+    }
+
+    /// Writes ip, registers and stack, but not memory, so they can be put
+    /// back onto a freshly loaded copy of the same program with
+    /// `load_cpu_state`. Words are little-endian: ip, the registers, the
+    /// stack depth as 32 bits and the stack from top to bottom.
+    pub fn save_cpu_state<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        out.write_u16::<LittleEndian>(self.ip)?;
+        for &value in &self.registers {
+            out.write_u16::<LittleEndian>(value)?;
+        }
+        out.write_u32::<LittleEndian>(self.stack.len() as u32)?;
+        for &value in &self.stack {
+            out.write_u16::<LittleEndian>(value)?;
+        }
+        out.flush()
+    }
+
+    /// Restores what `save_cpu_state` wrote. Memory is left alone and is
+    /// assumed to match the program the state was saved from. Nothing
+    /// changes if reading fails.
+    pub fn load_cpu_state<R: Read>(&mut self, input: &mut R) -> io::Result<()> {
+        let ip = input.read_u16::<LittleEndian>()?;
+        let mut registers = vec![0; self.registers.len()];
+        input.read_u16_into::<LittleEndian>(&mut registers)?;
+        let depth = input.read_u32::<LittleEndian>()?;
+        let mut stack = LinkedList::new();
+        for _ in 0..depth {
+            stack.push_back(input.read_u16::<LittleEndian>()?);
+        }
+        self.ip = ip;
+        self.registers = registers;
+        self.stack = stack;
+        Ok(())
+    }
+
+    /// Writes memory as `dump_memory` does, followed by what
+    /// `save_cpu_state` writes: everything needed to carry on from here.
+    pub fn save_snapshot<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        dump_memory(&self.memory, out)?;
+        self.save_cpu_state(out)
+    }
+
+    /// Restores what `save_snapshot` wrote. Nothing changes if reading
+    /// fails.
+    pub fn load_snapshot<R: Read>(&mut self, input: &mut R) -> io::Result<()> {
+        let mut memory = vec![0; self.memory.len()];
+        input.read_u16_into::<LittleEndian>(&mut memory)?;
+        self.load_cpu_state(input)?;
+        self.memory = memory;
+        Ok(())
+    }
+
+    /// A machine with `words` loaded from address 0 and the rest of memory
+    /// zeroed, or `None` if they do not fit in memory.
+    ///
+    /// ```
+    /// use synacor_challenge::{asm::assemble, Machine};
+    ///
+    /// let mut machine = Machine::from_words(&assemble("add r0 2 3\nhalt").unwrap()).unwrap();
+    /// machine.run().unwrap();
+    /// assert_eq!(machine.registers[0], 5);
+    /// ```
+    pub fn from_words(words: &[u16]) -> Option<Machine> {
+        if words.len() > MEMORY_SIZE {
+            return None;
+        }
+        let mut memory = vec![0; MEMORY_SIZE];
+        memory[..words.len()].copy_from_slice(words);
+        Some(Machine::new(memory))
+    }
+
+    /// Executes the instruction at `ip`. Returns `Ok(false)` once the
+    /// machine has halted.
+    pub fn step(&mut self) -> Result<bool, VmError> {
+        if self.ip_history.len() == IP_HISTORY_LEN {
+            self.ip_history.pop_front();
+        }
+        self.ip_history.push_back(self.ip);
+        let instr: Instruction = match get_op(self) {
+            Some(instr) => instr,
+            None => return self.illegal_opcode(),
+        };
+        if let Some(operand) = self.invalid_operand(instr) {
+            return Err(VmError::InvalidOperand {
+                ip: self.ip,
+                operand,
+            });
+        }
+        if self.strict {
+            self.check_invariants(instr)?;
+        }
+        self.check_memory_writes(instr)?;
+        self.check_next_ip(instr)?;
+        if let Some(tracer) = &mut self.tracer {
+            tracer.trace(self.ip, instr, &self.registers);
+        }
+        if let Some(csv) = &mut self.register_csv {
+            csv.record(self.ip, &self.registers);
+        }
+        if let Some(counts) = &mut self.opcode_counts {
+            counts[instr.opcode() as usize] += 1;
+        }
+        if self.profile.is_some() {
+            let target = match instr {
+                Instruction::Call(a) => get_oprnd_value(self, a),
+                _ => 0,
+            };
+            if let Some(profile) = &mut self.profile {
+                profile.record(instr, target);
+            }
+        }
+
+        match instr {
+            Instruction::Halt => return Ok(false),
+            Instruction::Set(a, b) => {
+                let addr: Address = self.address(a).unwrap();
+                if let Address::Mem(_) = addr {
+                    return Err(VmError::NonRegisterDestination {
+                        ip: self.ip,
+                        operand: a,
+                    });
+                }
+                let value = get_oprnd_value(self, b);
+                write_mem(self, addr, value);
+                self.ip += 3;
+            }
+            Instruction::Push(a) => {
+                let value = get_oprnd_value(self, a);
+                self.check_stack_depth()?;
+                self.stack.push_front(value);
+                self.record(Change::Push(value));
+                self.ip += 2;
+            }
+            Instruction::Pop(a) => {
+                let address = self.address(a).unwrap();
+                let value = self.stack.pop_front().unwrap();
+                self.record(Change::Pop(value));
+                write_mem(self, address, value);
+                self.ip += 2;
+            }
+            Instruction::Eq(_, _, _) | Instruction::Gt(_, _, _) => {
+                comp_op(self, instr)?;
+                self.ip += 4;
+            }
+            Instruction::Jmp(a) => self.ip = get_oprnd_value(self, a),
+            Instruction::Jt(a, b) => {
+                if get_oprnd_value(self, a) != 0 {
+                    self.ip = get_oprnd_value(self, b);
+                } else {
+                    self.ip += 3;
+                }
+            }
+            Instruction::Jf(a, b) => {
+                if get_oprnd_value(self, a) == 0 {
+                    self.ip = get_oprnd_value(self, b);
+                } else {
+                    self.ip += 3;
+                }
+            }
+            Instruction::Add(_, _, _) => {
+                bin_op(self, |x, y| x + y, instr);
+                self.ip += 4;
+            }
+            Instruction::Mult(_, _, _) => {
+                bin_op(self, |x, y| x * y, instr);
+                self.ip += 4;
+            }
+            Instruction::Mod(_, _, _) => {
+                bin_op(self, |x, y| x % y, instr);
+                self.ip += 4;
+            }
+            Instruction::And(_, _, _) => {
+                bin_op(self, |x, y| x & y, instr);
+                self.ip += 4;
+            }
+            Instruction::Or(_, _, _) => {
+                bin_op(self, |x, y| x | y, instr);
+                self.ip += 4;
+            }
+            Instruction::Not(a, b) => {
+                let addr: Address = self.address(a).unwrap();
+                // Inverting only the low 15 bits keeps the result a valid
+                // number, also for a word with bit 15 set.
+                let value: u16 = !get_oprnd_value(self, b) & 0x7FFFu16;
+                write_mem(self, addr, value);
+                self.ip += 3;
+            }
+            Instruction::Rmem(a, b) => {
+                let addr_a: Address = self.address(a).unwrap();
+                let addr_b: Address = self.address(get_oprnd_value(self, b)).unwrap();
+                let value: u16 = read_mem(self, addr_b);
+                write_mem(self, addr_a, value);
+                self.ip += 3;
+            }
+            Instruction::Wmem(a, b) => {
+                let addr: Address = self.address(get_oprnd_value(self, a)).unwrap();
+                let value = get_oprnd_value(self, b);
+                write_mem(self, addr, value);
+                self.ip += 3;
+            }
+            // The target is resolved like any other operand, so `call r0`
+            // jumps to the value of r0. The return address is that of the
+            // next instruction, ip + 2, whichever form the target takes.
+            Instruction::Call(a) => {
+                self.check_stack_depth()?;
+                self.stack.push_front(self.ip + 2);
+                self.record(Change::Push(self.ip + 2));
+                self.ip = get_oprnd_value(self, a);
+                if self.warn_unbalanced_stack {
+                    self.frames.push((self.ip, self.stack.len()));
+                }
+            }
+            Instruction::Ret => {
+                if self.warn_unbalanced_stack {
+                    self.check_frame();
+                }
+                let value = self.stack.pop_front().unwrap();
+                self.record(Change::Pop(value));
+                self.ip = value;
+            }
+            Instruction::Out(a) => {
+                let value = get_oprnd_value(self, a);
+                if self.ascii_output && value > 127 {
+                    return Err(VmError::NonAsciiOutput { ip: self.ip, value });
+                }
+                let byte = value as u8;
+                self.output
+                    .write_all(&[byte])
+                    .expect("Could not write output");
+                if self.prompt.len() >= MAX_PROMPT_LEN {
+                    self.prompt.drain(..MAX_PROMPT_LEN / 2);
+                }
+                self.prompt.push(byte);
+                if let Some(log) = &mut self.event_log {
+                    log.record(self.ip, EventKind::Out, byte);
+                }
+                self.ip += 2;
+            }
+            Instruction::In(a) => {
+                let target = match self.address(a) {
+                    Some(target @ Address::Reg(_)) => target,
+                    _ => {
+                        return Err(VmError::BadInputTarget {
+                            ip: self.ip,
+                            operand: a,
+                        })
+                    }
+                };
+                if self.input_queue.is_empty() && self.nonblocking_input {
+                    return Err(VmError::NeedsInput { ip: self.ip });
+                }
+                if self.input_queue.is_empty() {
+                    if let Some(mut callback) = self.input_hook.take() {
+                        callback(self);
+                        self.input_hook = Some(callback);
+                    }
+                    let input = self
+                        .input
+                        .read_input(&String::from_utf8_lossy(&self.prompt))
+                        .expect("Did not enter a correct string");
+                    self.prompt.clear();
+
+                    self.input_queue.extend(input);
+                }
+
+                // An empty chunk means the input is exhausted: stop as if
+                // the program had halted, leaving ip at the `in`.
+                let byte = match self.input_queue.pop_front() {
+                    Some(byte) => byte,
+                    None => return Ok(false),
+                };
+                self.record(Change::Input(byte));
+                if let Some(log) = &mut self.event_log {
+                    log.record(self.ip, EventKind::In, byte);
+                }
+                write_mem(self, target, byte as u16);
+
+                self.ip += 2;
+            }
+            Instruction::Noop => self.ip += 1,
+        }
+
+        self.finish_step();
+        Ok(true)
+    }
+
+    fn finish_step(&mut self) {
+        self.instructions_executed += 1;
+        if let Some((interval, mut callback)) = self.heartbeat.take() {
+            if self.instructions_executed.is_multiple_of(interval) {
+                callback(self);
+            }
+            self.heartbeat = Some((interval, callback));
+        }
+    }
+
+    fn illegal_opcode(&mut self) -> Result<bool, VmError> {
+        // With ip set past the end of memory there is no word to decode.
+        let word = match self.memory.get(self.ip as usize) {
+            Some(&word) => word,
+            None => return Err(VmError::PcOverflow { ip: self.ip }),
+        };
+        if opcode_arity(word).is_some() {
+            return Err(VmError::TruncatedInstruction { ip: self.ip });
+        }
+        match self.on_illegal_opcode {
+            _ if self.strict => Err(VmError::IllegalOpcode { ip: self.ip, word }),
+            IllegalOpcodePolicy::Error => Err(VmError::IllegalOpcode { ip: self.ip, word }),
+            IllegalOpcodePolicy::SkipWord => {
+                if let Some(tracer) = &mut self.tracer {
+                    tracer.skipped(self.ip, word);
+                }
+                self.ip += 1;
+                self.finish_step();
+                Ok(true)
+            }
+        }
+    }
+
+    /// The first operand of `instr` at ip that is neither a number nor one
+    /// of the registers. Read straight from memory to keep this cheap.
+    fn invalid_operand(&self, instr: Instruction) -> Option<u16> {
+        let start = self.ip as usize + 1;
+        self.memory[start..start + instr.size() as usize - 1]
+            .iter()
+            .copied()
+            .find(|&oprnd| self.address(oprnd).is_none())
+    }
+
+    /// The checks `strict` turns on, made before `instr` at `ip` executes.
+    fn check_invariants(&self, instr: Instruction) -> Result<(), VmError> {
+        let ip = self.ip;
+        if let Some(operand) = instr.destination() {
+            if let Some(Address::Mem(_)) = self.address(operand) {
+                return Err(VmError::NonRegisterDestination { ip, operand });
+            }
+        }
+        let mut values: Vec<u16> = instr
+            .sources()
+            .into_iter()
+            .map(|oprnd| get_oprnd_value(self, oprnd))
+            .collect();
+        if let Instruction::Rmem(_, _) = instr {
+            values.extend(self.memory.get(values[0] as usize).copied());
+        }
+        if let Some(&value) = values
+            .iter()
+            .find(|&&value| value as usize >= INTEGER_RANGE)
+        {
+            return Err(VmError::ValueOutOfRange { ip, value });
+        }
+        if let Instruction::Out(_) = instr {
+            if values[0] > 127 {
+                return Err(VmError::NonAsciiOutput {
+                    ip,
+                    value: values[0],
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Ends the innermost frame at a `ret`, warning if the stack is not
+    /// back at the depth the call left it at. A `ret` without a tracked
+    /// call, e.g. to an address pushed by hand, is ignored.
+    fn check_frame(&mut self) {
+        if let Some((entry, depth)) = self.frames.pop() {
+            if self.stack.len() != depth {
+                eprintln!(
+                    "unbalanced stack at ip {}: subroutine {} returns with {} {} entries",
+                    self.ip,
+                    entry,
+                    self.stack.len().abs_diff(depth),
+                    if self.stack.len() > depth {
+                        "extra"
+                    } else {
+                        "missing"
+                    }
+                );
+            }
+        }
+    }
+
+    /// Fails if `instr` would go on to the address after it, or return
+    /// there, when that is outside memory, and likewise if it jumps, calls
+    /// or returns to an address outside memory.
+    fn check_next_ip(&self, instr: Instruction) -> Result<(), VmError> {
+        let (continues, target) = match instr {
+            Instruction::Halt => (false, None),
+            Instruction::Jmp(a) => (false, Some(get_oprnd_value(self, a))),
+            Instruction::Jt(a, b) if get_oprnd_value(self, a) != 0 => {
+                (false, Some(get_oprnd_value(self, b)))
+            }
+            Instruction::Jf(a, b) if get_oprnd_value(self, a) == 0 => {
+                (false, Some(get_oprnd_value(self, b)))
+            }
+            Instruction::Call(a) => (true, Some(get_oprnd_value(self, a))),
+            Instruction::Ret => (false, self.stack.front().copied()),
+            _ => (true, None),
+        };
+        let past_end = continues && self.ip as usize + instr.size() as usize >= ADDRESS_RANGE;
+        if past_end || target.is_some_and(|target| target as usize >= ADDRESS_RANGE) {
+            return Err(VmError::PcOverflow { ip: self.ip });
+        }
+        Ok(())
+    }
+
+    fn check_memory_writes(&self, instr: Instruction) -> Result<(), VmError> {
+        let writes_memory = match instr {
+            Instruction::Wmem(_, _) => true,
+            // A `set` to memory fails with `NonRegisterDestination` instead
+            // of writing.
+            Instruction::Set(_, _) => false,
+            _ => matches!(
+                instr
+                    .destination()
+                    .and_then(|operand| self.address(operand)),
+                Some(Address::Mem(_))
+            ),
+        };
+        match self.max_memory_writes {
+            Some(max) if writes_memory && self.memory_writes >= max => {
+                Err(VmError::TooManyWrites {
+                    ip: self.ip,
+                    writes: self.memory_writes,
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn check_stack_depth(&self) -> Result<(), VmError> {
+        match self.max_stack_depth {
+            Some(max) if self.stack.len() >= max => Err(VmError::StackOverflow {
+                ip: self.ip,
+                depth: self.stack.len() + 1,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Runs until the program waits for input it does not have yet, or
+    /// halts, and returns what it printed on the way. Only input queued
+    /// with `push_input` is used; `input` is never asked, so this does not
+    /// block whatever `nonblocking_input` says. A frontend alternates this
+    /// with `push_input`. Calling it again without pushing input returns
+    /// right away with empty output. Output still goes to `output` as usual.
+    pub fn run_until_input(&mut self) -> Result<RunSegment, VmError> {
+        let mut output = Vec::new();
+        loop {
+            let instr = get_op(self);
+            if self.needs_input() {
+                return Ok(RunSegment {
+                    output: String::from_utf8_lossy(&output).into_owned(),
+                    end: SegmentEnd::Input,
+                });
+            }
+            if !self.step()? {
+                return Ok(RunSegment {
+                    output: String::from_utf8_lossy(&output).into_owned(),
+                    end: SegmentEnd::Halted,
+                });
+            }
+            if let Some(Instruction::Out(_)) = instr {
+                output.extend(self.prompt.last());
+            }
+        }
+    }
+
+    pub fn run(&mut self) -> Result<(), VmError> {
+        while self.step()? {}
+        Ok(())
+    }
+
+    /// Like `run`, but any panic escaping the step loop is turned into
+    /// `VmError::InternalPanic` carrying the `ip` it happened at, so batch
+    /// and fuzz runs survive malformed programs. This is belt-and-suspenders
+    /// while the remaining `unwrap`s are replaced by proper errors, not a
+    /// substitute for them.
+    pub fn run_safe(&mut self) -> Result<(), VmError> {
+        match panic::catch_unwind(AssertUnwindSafe(|| self.run())) {
+            Ok(result) => result,
+            Err(_) => Err(VmError::InternalPanic { ip: self.ip }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::Script;
+    use crate::output::Capture;
+    use std::{env, fs, process};
+
+    /// An input source that has nothing more to give.
+    struct Exhausted;
+
+    impl InputSource for Exhausted {
+        fn read_input(&mut self, _prompt: &str) -> io::Result<Vec<u8>> {
+            Ok(Vec::new())
+        }
+    }
+
+    /// A machine running `words`, printing into the returned capture.
+    fn capturing(words: &[u16]) -> (Machine, Capture) {
+        let mut machine = Machine::from_words(words).unwrap();
+        let capture = Capture::new();
+        machine.output = Box::new(capture.clone());
+        machine.input = Box::new(Exhausted);
+        (machine, capture)
+    }
+
+    #[test]
+    fn script_without_final_newline_runs_to_end_of_input() {
+        let path = env::temp_dir().join(format!("synacor-script-{}", process::id()));
+        fs::write(&path, "look\nn\ninv").unwrap();
+        // 0: in r0; 2: out r0; 4: jmp 0
+        let (mut machine, capture) = capturing(&[20, 32768, 19, 32768, 6, 0]);
+        machine.input =
+            Box::new(Script::from_file(path.to_str().unwrap(), Box::new(Exhausted)).unwrap());
+        let result = machine.run();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(result, Ok(()));
+        assert_eq!(capture.bytes(), b"look\nn\ninv\n");
+        assert_eq!(machine.ip, 0);
+    }
+
+    #[test]
+    fn illegal_opcode_policies() {
+        // 0: out 'a'; 2: an illegal word; 3: out 'b'; 5: halt
+        let words = [19, 97, 22, 19, 98, 0];
+
+        let (mut machine, capture) = capturing(&words);
+        machine.on_illegal_opcode = IllegalOpcodePolicy::Error;
+        assert_eq!(
+            machine.run(),
+            Err(VmError::IllegalOpcode { ip: 2, word: 22 })
+        );
+        assert_eq!(capture.bytes(), b"a");
+
+        let (mut machine, capture) = capturing(&words);
+        machine.on_illegal_opcode = IllegalOpcodePolicy::SkipWord;
+        assert_eq!(machine.run(), Ok(()));
+        assert_eq!(capture.bytes(), b"ab");
+    }
+
+    #[test]
+    fn ip_past_end_of_memory_is_an_error() {
+        let (mut machine, _) = capturing(&[0]);
+        for policy in [IllegalOpcodePolicy::Error, IllegalOpcodePolicy::SkipWord] {
+            machine.on_illegal_opcode = policy;
+            machine.ip = MEMORY_SIZE as u16;
+            assert_eq!(machine.step(), Err(VmError::PcOverflow { ip: 32768 }));
+        }
+    }
+
+    #[test]
+    fn jump_past_end_of_memory_is_an_error() {
+        // 0: noop; 1: jmp r0
+        let (mut machine, _) = capturing(&[21, 6, 32768]);
+        machine.registers[0] = 32768;
+        assert_eq!(machine.step(), Ok(true));
+        assert_eq!(machine.step(), Err(VmError::PcOverflow { ip: 1 }));
+        assert_eq!(machine.ip, 1);
+    }
+
+    #[test]
+    fn call_through_register_returns_after_the_call() {
+        let mut words = vec![0; 101];
+        words[..2].copy_from_slice(&[17, 32768]); // 0: call r0; 2: halt
+        words[100] = 18; // 100: ret
+        let (mut machine, _) = capturing(&words);
+        machine.registers[0] = 100;
+
+        assert_eq!(machine.step(), Ok(true));
+        assert_eq!(machine.ip, 100);
+        assert_eq!(machine.stack_snapshot(), vec![2]);
+
+        assert_eq!(machine.step(), Ok(true));
+        assert_eq!(machine.ip, 2);
+        assert_eq!(machine.stack_depth(), 0);
+        assert_eq!(machine.step(), Ok(false));
+    }
+
+    #[test]
+    fn in_stores_the_byte_in_a_register() {
+        let (mut machine, _) = capturing(&[20, 32768, 0]); // in r0; halt
+        machine.push_input(b"x\n");
+        assert_eq!(machine.step(), Ok(true));
+        assert_eq!(machine.registers[0], u16::from(b'x'));
+        assert_eq!(machine.ip, 2);
+    }
+
+    #[test]
+    fn in_to_memory_is_an_error() {
+        let (mut machine, _) = capturing(&[20, 5, 0]); // in 5; halt
+        machine.push_input(b"x\n");
+        assert_eq!(
+            machine.step(),
+            Err(VmError::BadInputTarget { ip: 0, operand: 5 })
+        );
+        assert_eq!(machine.memory[5], 0);
+    }
+
+    #[test]
+    fn add_in_the_last_word_is_truncated() {
+        let (mut machine, _) = capturing(&[]);
+        let last = MEMORY_SIZE - 1;
+        machine.memory[last] = 9; // add, with no room for its operands
+        machine.ip = last as u16;
+        assert_eq!(
+            machine.step(),
+            Err(VmError::TruncatedInstruction { ip: last as u16 })
+        );
+    }
+
+    #[test]
+    fn clone_is_independent() {
+        let (mut original, _) = capturing(&[2, 7, 0]); // push 7; halt
+        original.step().unwrap();
+        let mut clone = original.clone();
+        assert!(clone.state_eq(&original));
+        assert_eq!(clone.input.name(), StdinLines.name());
+
+        clone.memory[1] = 8;
+        clone.registers[0] = 1;
+        clone.set_stack_entry(0, 9);
+        clone.ip = 0;
+        assert_eq!(original.memory[1], 7);
+        assert_eq!(original.registers[0], 0);
+        assert_eq!(original.stack_snapshot(), vec![7]);
+        assert_eq!(original.ip, 2);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn json_round_trip() {
+        let instr = Instruction::Add(32768, 32769, 4);
+        let json = serde_json::to_string(&instr).unwrap();
+        assert_eq!(serde_json::from_str::<Instruction>(&json).unwrap(), instr);
+
+        let map = analysis::memory_map(&asm::assemble("out 'a'\nhalt").unwrap());
+        let json = serde_json::to_string(&map).unwrap();
+        assert_eq!(
+            serde_json::from_str::<analysis::MemoryMap>(&json).unwrap(),
+            map
+        );
+    }
+
+    #[test]
+    fn dump_reloads_as_the_same_program() {
+        let program: [u16; 8] = [9, 32768, 1, 2, 19, 32768, 0, 0xABCD];
+        let bytes: Vec<u8> = program.iter().flat_map(|word| word.to_le_bytes()).collect();
+        let memory = load_memory(bytes).unwrap();
+        let mut machine = Machine::new(memory.clone());
+        machine.output = Box::new(io::sink());
+        machine.run().unwrap();
+
+        let mut dump = Vec::new();
+        dump_memory(&machine.memory, &mut dump).unwrap();
+        assert_eq!(dump.len(), MEMORY_SIZE * 2);
+        let reloaded = load_memory(dump).unwrap();
+        assert_eq!(reloaded, memory);
+        assert!(Machine::new(reloaded).state_eq(&Machine::new(memory)));
+    }
+
+    #[test]
+    fn run_until_input_splits_a_dialog() {
+        let program = "out 'A'\nin r0\nin r1\nout r0\nout 'B'\nin r0\nin r1\nout r0\nhalt";
+        let (mut machine, _) = capturing(&asm::assemble(program).unwrap());
+
+        let first = machine.run_until_input().unwrap();
+        assert_eq!(first.output, "A");
+        assert_eq!(first.end, SegmentEnd::Input);
+
+        machine.push_input(b"x\n");
+        let second = machine.run_until_input().unwrap();
+        assert_eq!(second.output, "xB");
+        assert_eq!(second.end, SegmentEnd::Input);
+
+        machine.push_input(b"y\n");
+        let last = machine.run_until_input().unwrap();
+        assert_eq!(last.output, "y");
+        assert_eq!(last.end, SegmentEnd::Halted);
+    }
+
+    /// The error `step` gives for `words` in strict mode.
+    fn strict_error(words: &[u16]) -> Result<bool, VmError> {
+        let (mut machine, _) = capturing(words);
+        machine.strict = true;
+        machine.registers[1] = 32768;
+        machine.push_input(b"x\n");
+        machine.step()
+    }
+
+    #[test]
+    fn strict_rejects_invalid_operands() {
+        assert_eq!(
+            strict_error(&[19, 32776]),
+            Err(VmError::InvalidOperand {
+                ip: 0,
+                operand: 32776
+            })
+        );
+    }
+
+    #[test]
+    fn strict_rejects_non_ascii_output() {
+        assert_eq!(
+            strict_error(&[19, 200]),
+            Err(VmError::NonAsciiOutput { ip: 0, value: 200 })
+        );
+    }
+
+    #[test]
+    fn strict_rejects_non_register_destinations() {
+        // set 5 1; in 5; rmem 5 0; not 5 0
+        for words in [&[1, 5, 1][..], &[20, 5], &[15, 5, 0], &[14, 5, 0]] {
+            assert_eq!(
+                strict_error(words),
+                Err(VmError::NonRegisterDestination { ip: 0, operand: 5 })
+            );
+        }
+    }
+
+    #[test]
+    fn strict_rejects_values_out_of_range() {
+        // add r0 r1 1, with r1 holding 32768
+        assert_eq!(
+            strict_error(&[9, 32768, 32769, 1]),
+            Err(VmError::ValueOutOfRange {
+                ip: 0,
+                value: 32768
+            })
+        );
+    }
+
+    #[test]
+    fn strict_rejects_illegal_opcodes_whatever_the_policy() {
+        let (mut machine, _) = capturing(&[22]);
+        machine.strict = true;
+        machine.on_illegal_opcode = IllegalOpcodePolicy::SkipWord;
+        assert_eq!(
+            machine.step(),
+            Err(VmError::IllegalOpcode { ip: 0, word: 22 })
+        );
+    }
+
+    #[test]
+    fn reset_matches_a_fresh_machine() {
+        let words = asm::assemble("push 3\nset r2 4\nwmem 100 5\nhalt").unwrap();
+        let (mut machine, _) = capturing(&words);
+        let fresh = Machine::from_words(&words).unwrap();
+        machine.run().unwrap();
+        assert!(!machine.state_eq(&fresh));
+
+        machine.reset(fresh.memory.clone());
+        assert!(machine.state_eq(&fresh));
+    }
+
+    #[test]
+    fn set_to_memory_is_an_error() {
+        let (mut machine, _) = capturing(&[1, 5, 1]); // set 5 1
+        machine.max_memory_writes = Some(0);
+        assert_eq!(
+            machine.step(),
+            Err(VmError::NonRegisterDestination { ip: 0, operand: 5 })
+        );
+        assert_eq!(machine.memory[5], 0);
+        assert_eq!(machine.memory_writes(), 0);
+
+        let (mut machine, _) = capturing(&[16, 5, 1]); // wmem 5 1
+        machine.max_memory_writes = Some(0);
+        assert_eq!(
+            machine.step(),
+            Err(VmError::TooManyWrites { ip: 0, writes: 0 })
+        );
+    }
+
+    #[test]
+    fn register_count_moves_the_operand_boundary() {
+        assert_eq!(get_addr(32775, NUMBER_OF_REGISTERS), Some(Address::Reg(7)));
+        assert_eq!(get_addr(32776, NUMBER_OF_REGISTERS), None);
+        assert_eq!(get_addr(32776, 9), Some(Address::Reg(8)));
+
+        let words = [1, 32776, 5, 0]; // set r8 5; halt
+        let (mut machine, _) = capturing(&words);
+        assert_eq!(
+            machine.step(),
+            Err(VmError::InvalidOperand {
+                ip: 0,
+                operand: 32776
+            })
+        );
+
+        let (mut machine, _) = capturing(&words);
+        machine.set_register_count(9);
+        machine.run().unwrap();
+        assert_eq!(machine.registers[8], 5);
+    }
+}
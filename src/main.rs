@@ -1,323 +1,1209 @@
-use byteorder::{LittleEndian, ReadBytesExt};
-use std::collections::LinkedList;
-use std::fs;
-use std::io::{stdin, Cursor};
-use std::vec::IntoIter;
-
-const ADDRESS_RANGE: usize = 1 << 15;
-const INTEGER_RANGE: usize = 1 << 15;
-const MEMORY_SIZE: usize = 1 << 15;
-const NUMBER_OF_REGISTERS: usize = 8;
-
-struct Machine<'a> {
-    memory: Box<Vec<u16>>,
-    registers: Box<Vec<u16>>,
-    stack: &'a mut LinkedList<u16>,
-    ip: u16,
-}
-
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-enum Instruction {
-    Halt,
-    Set(u16, u16),
-    Push(u16),
-    Pop(u16),
-    Eq(u16, u16, u16),
-    Gt(u16, u16, u16),
-    Jmp(u16),
-    Jt(u16, u16),
-    Jf(u16, u16),
-    Add(u16, u16, u16),
-    Mult(u16, u16, u16),
-    Mod(u16, u16, u16),
-    And(u16, u16, u16),
-    Or(u16, u16, u16),
-    Not(u16, u16),
-    Rmem(u16, u16),
-    Wmem(u16, u16),
-    Call(u16),
-    Ret,
-    Out(u16),
-    In(u16),
-    Noop,
-}
-
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-enum Address {
-    Mem(usize),
-    Reg(usize),
-}
-
-fn read_mem(mach: &mut Machine, address: Address) -> u16 {
-    match address {
-        Address::Mem(addr) => mach.memory[addr],
-        Address::Reg(addr) => mach.registers[addr],
-    }
-}
-
-fn write_mem(mach: &mut Machine, address: Address, value: u16) {
-    match address {
-        Address::Mem(addr) => mach.memory[addr] = value,
-        Address::Reg(addr) => {
-            mach.registers[addr] = value as u16;
-        }
-    }
-}
-
-fn get_oprnd_value(mut mach: &mut Machine, raw_addr: u16) -> u16 {
-    let value: u16 = read_mem(&mut mach, get_addr(raw_addr).unwrap());
-    if (value as usize) < INTEGER_RANGE {
-        return value;
-    } else {
-        read_mem(&mut mach, get_addr(value).unwrap())
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, IsTerminal, Write};
+use std::path::Path;
+use std::process;
+use std::time::Duration;
+use synacor_challenge::analysis::{
+    find_call_targets, find_first_input, find_indirect_calls, find_indirect_writes, find_writers,
+    labeled_listing, linear_decode, listing, memory_map, misaligned_targets, string_map,
+    verify_bytecode, StringKind, MIN_STRING_LEN,
+};
+use synacor_challenge::asm::encode_line;
+use synacor_challenge::debugger::{self, Debugger};
+use synacor_challenge::events::{self, EventKind, EventLog};
+use synacor_challenge::input::{self, Autoplay, InputSource, Script};
+use synacor_challenge::output::{
+    Capture, CodeCollector, LineEndings, Newline, ScrollDelay, Scrollback, Shared, Tee, Utf8Output,
+};
+use synacor_challenge::preset::Preset;
+use synacor_challenge::puzzles::teleporter;
+use synacor_challenge::report::write_report;
+use synacor_challenge::symbols::{self, Symbols};
+use synacor_challenge::trace::{CallProfile, RegisterCsv, TraceComparator, Tracer};
+use synacor_challenge::*;
+
+enum Mode {
+    Run,
+    DumpStringsSorted,
+    DecodeOne(usize),
+    Functions,
+    Writers(usize),
+    VerifyLengths,
+    MemoryMap,
+    DryRun,
+    FindInput,
+    ExportDecoded(String),
+    WriteGolden(String),
+    ValidateAgainst(String),
+    Listing(String),
+    Report(String),
+    AsmRepl,
+}
+
+struct Options {
+    path: String,
+    mode: Mode,
+    safe: bool,
+    debug: bool,
+    warn_overflow: bool,
+    warn_unbalanced_stack: bool,
+    max_stack_depth: Option<usize>,
+    autoplay: Option<String>,
+    input: Option<String>,
+    dump_memory: Option<String>,
+    trace: bool,
+    trace_file: Option<String>,
+    trace_filter: Option<String>,
+    trace_regs: u8,
+    scroll_delay: u64,
+    tee: Option<String>,
+    hints: bool,
+    event_log: Option<String>,
+    verify_bytecode: bool,
+    registers_csv: Option<String>,
+    collect_codes: bool,
+    skip_illegal: bool,
+    strict: bool,
+    init_state: Option<String>,
+    compare_trace: Option<String>,
+    pause_on_halt: bool,
+    show_exec: bool,
+    cpu_state: Option<String>,
+    symbols: Option<String>,
+    breakpoints: Option<String>,
+    expect: Vec<String>,
+    random_registers: Option<u64>,
+    verify_replay: Option<String>,
+    opcode_histogram: Option<String>,
+    segments: Vec<(String, usize)>,
+    flamegraph: Option<String>,
+    reachable_walk: bool,
+    golden_steps: u64,
+    step_dump: Option<(u64, String)>,
+    seed_stack: Vec<u16>,
+    utf8_output: bool,
+    autosave: Option<String>,
+    autosave_keep: usize,
+    scrollback: usize,
+    restore: Option<String>,
+    replay_events: Option<String>,
+    replay_events_strict: bool,
+    truncate: bool,
+    max_memory_writes: Option<u64>,
+    hot_functions: bool,
+    transcript_newline: Newline,
+}
+
+/// The program is the positional argument if given, else `$SYNACOR_BIN`,
+/// else `challenge.bin`.
+fn parse_args() -> Options {
+    let mut options = Options {
+        path: env::var("SYNACOR_BIN").unwrap_or_else(|_| String::from("challenge.bin")),
+        mode: Mode::Run,
+        safe: false,
+        debug: false,
+        warn_overflow: false,
+        warn_unbalanced_stack: false,
+        max_stack_depth: None,
+        autoplay: None,
+        input: None,
+        dump_memory: None,
+        trace: false,
+        trace_file: None,
+        trace_filter: None,
+        trace_regs: 0,
+        scroll_delay: 0,
+        tee: None,
+        hints: false,
+        event_log: None,
+        verify_bytecode: false,
+        registers_csv: None,
+        collect_codes: false,
+        skip_illegal: false,
+        strict: false,
+        init_state: None,
+        compare_trace: None,
+        pause_on_halt: false,
+        show_exec: false,
+        cpu_state: None,
+        symbols: None,
+        breakpoints: None,
+        expect: Vec::new(),
+        random_registers: None,
+        verify_replay: None,
+        opcode_histogram: None,
+        segments: Vec::new(),
+        flamegraph: None,
+        reachable_walk: false,
+        golden_steps: GOLDEN_STEPS,
+        step_dump: None,
+        seed_stack: Vec::new(),
+        utf8_output: false,
+        autosave: None,
+        autosave_keep: 1,
+        scrollback: DEFAULT_SCROLLBACK,
+        restore: None,
+        replay_events: None,
+        replay_events_strict: false,
+        truncate: false,
+        max_memory_writes: None,
+        hot_functions: false,
+        transcript_newline: Newline::Raw,
+    };
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--dump-strings-sorted" => options.mode = Mode::DumpStringsSorted,
+            "--decode-one" => options.mode = Mode::DecodeOne(number_arg(&arg, args.next())),
+            "--functions" => options.mode = Mode::Functions,
+            "--writers" => options.mode = Mode::Writers(number_arg(&arg, args.next())),
+            "--verify-instruction-lengths" => options.mode = Mode::VerifyLengths,
+            "--memory-map" => options.mode = Mode::MemoryMap,
+            "--dry-run" => options.mode = Mode::DryRun,
+            "--find-input" => options.mode = Mode::FindInput,
+            "--export-decoded" => options.mode = Mode::ExportDecoded(parse_path(&arg, args.next())),
+            "--export-walk" => match parse_path(&arg, args.next()).as_str() {
+                "linear" => options.reachable_walk = false,
+                "reachable" => options.reachable_walk = true,
+                walk => usage_error(&format!(
+                    "--export-walk: unknown walk {} (expected linear or reachable)",
+                    walk
+                )),
+            },
+            "--transcript-newline" => match parse_path(&arg, args.next()).as_str() {
+                "raw" => options.transcript_newline = Newline::Raw,
+                "lf" => options.transcript_newline = Newline::Lf,
+                "crlf" => options.transcript_newline = Newline::Crlf,
+                newline => usage_error(&format!(
+                    "--transcript-newline: unknown line ending {} (expected lf, crlf or raw)",
+                    newline
+                )),
+            },
+            "--listing" => options.mode = Mode::Listing(parse_path(&arg, args.next())),
+            "--report" => options.mode = Mode::Report(parse_path(&arg, args.next())),
+            "--asm-repl" => options.mode = Mode::AsmRepl,
+            "--write-golden" => options.mode = Mode::WriteGolden(parse_path(&arg, args.next())),
+            "--golden-steps" => options.golden_steps = number_arg(&arg, args.next()) as u64,
+            "--validate-against" => {
+                options.mode = Mode::ValidateAgainst(parse_path(&arg, args.next()))
+            }
+            "--verify-bytecode" => options.verify_bytecode = true,
+            "--skip-illegal" => options.skip_illegal = true,
+            "--strict" => options.strict = true,
+            "--truncate" => options.truncate = true,
+            "--utf8-output" => options.utf8_output = true,
+            "--safe" => options.safe = true,
+            "--debug" => options.debug = true,
+            "--pause-on-halt" => options.pause_on_halt = true,
+            "--collect-codes" => options.collect_codes = true,
+            "--hints" => options.hints = true,
+            "--warn-overflow" => options.warn_overflow = true,
+            "--warn-unbalanced-stack" => options.warn_unbalanced_stack = true,
+            "--max-stack-depth" => options.max_stack_depth = Some(number_arg(&arg, args.next())),
+            "--max-memory-writes" => {
+                options.max_memory_writes = Some(number_arg(&arg, args.next()) as u64)
+            }
+            "--autoplay" => options.autoplay = Some(parse_path(&arg, args.next())),
+            "--trace" => options.trace = true,
+            "--show-exec" => options.show_exec = true,
+            "--trace-file" => options.trace_file = Some(parse_path(&arg, args.next())),
+            "--trace-filter" => options.trace_filter = Some(parse_path(&arg, args.next())),
+            "--scroll-delay" => options.scroll_delay = number_arg(&arg, args.next()) as u64,
+            "--tee" => options.tee = Some(parse_path(&arg, args.next())),
+            "--trace-regs" => options.trace_regs = u8::MAX,
+            "--trace-regs-filter" => {
+                options.trace_regs = register_mask(&parse_path(&arg, args.next()))
+            }
+            "--hot-functions" => options.hot_functions = true,
+            "--flamegraph" => options.flamegraph = Some(parse_path(&arg, args.next())),
+            "--opcode-histogram" => options.opcode_histogram = Some(parse_path(&arg, args.next())),
+            "--event-log" => options.event_log = Some(parse_path(&arg, args.next())),
+            "--dump-registers-csv" => options.registers_csv = Some(parse_path(&arg, args.next())),
+            "--cpu-state" => options.cpu_state = Some(parse_path(&arg, args.next())),
+            "--random-registers" => {
+                options.random_registers = Some(number_arg(&arg, args.next()) as u64)
+            }
+            "--seed-stack" => options.seed_stack = stack_values(&arg, args.next()),
+            "--autosave" => options.autosave = Some(parse_path(&arg, args.next())),
+            "--autosave-keep" => options.autosave_keep = number_arg(&arg, args.next()).max(1),
+            "--scrollback" => options.scrollback = number_arg(&arg, args.next()),
+            "--restore" => options.restore = Some(parse_path(&arg, args.next())),
+            "--init-state" => options.init_state = Some(parse_path(&arg, args.next())),
+            "--breakpoints" => options.breakpoints = Some(parse_path(&arg, args.next())),
+            "--symbols" => options.symbols = Some(parse_path(&arg, args.next())),
+            "--compare-trace" => options.compare_trace = Some(parse_path(&arg, args.next())),
+            "--step-dump" => options.step_dump = Some(step_dump_arg(&arg, args.next())),
+            "--segment" => options.segments.push(segment_arg(&arg, args.next())),
+            "--input" => options.input = Some(parse_path(&arg, args.next())),
+            "--verify-replay" => options.verify_replay = Some(parse_path(&arg, args.next())),
+            "--replay-events" => options.replay_events = Some(parse_path(&arg, args.next())),
+            "--replay-events-strict" => options.replay_events_strict = true,
+            "--expect" => options.expect.push(parse_path(&arg, args.next())),
+            "--dump-memory" => options.dump_memory = Some(parse_path(&arg, args.next())),
+            flag if flag.starts_with("--") => usage_error(&format!("Unknown option {}", flag)),
+            path => options.path = String::from(path),
+        }
     }
+
+    options
 }
 
-fn get_op(mut mach: &mut Machine) -> Option<Instruction> {
-    let raw_addr: u16 = mach.ip;
-    let addr = get_addr(raw_addr).unwrap();
-    let instr: u16 = read_mem(&mut mach, addr);
-    match instr {
-        0 | 18 | 21 => match instr {
-            0 => Some(Instruction::Halt),
-            18 => Some(Instruction::Ret),
-            21 => Some(Instruction::Noop),
-            _ => None,
+fn usage_error(message: &str) -> ! {
+    eprintln!("{}", message);
+    process::exit(2);
+}
+
+/// A `--step-dump` argument, `steps:path`.
+fn step_dump_arg(flag: &str, value: Option<String>) -> (u64, String) {
+    let value = parse_path(flag, value);
+    match value.split_once(':') {
+        Some((steps, path)) => match parse_number(steps) {
+            Some(steps) => (steps as u64, path.to_string()),
+            None => usage_error(&format!("{}: invalid step count {}", flag, steps)),
         },
-        2 | 3 | 6 | 17 | 19 | 20 => {
-            let a_raw: u16 = read_mem(&mut mach, get_addr(raw_addr + 1).unwrap());
-            let a: u16 = get_oprnd_value(&mut mach, raw_addr + 1);
-            match instr {
-                2 => Some(Instruction::Push(a)),
-                3 => Some(Instruction::Pop(a_raw)),
-                6 => Some(Instruction::Jmp(a)),
-                17 => Some(Instruction::Call(a)),
-                19 => Some(Instruction::Out(a)),
-                20 => Some(Instruction::In(a_raw)),
-                _ => None,
-            }
+        None => usage_error(&format!("{} expects steps:path", flag)),
+    }
+}
+
+/// A `--segment` argument, `path@offset`.
+fn segment_arg(flag: &str, value: Option<String>) -> (String, usize) {
+    let value = parse_path(flag, value);
+    match value.rsplit_once('@') {
+        Some((path, offset)) => match parse_number(offset) {
+            Some(offset) => (path.to_string(), offset),
+            None => usage_error(&format!("{}: invalid offset {}", flag, offset)),
+        },
+        None => usage_error(&format!("{} expects path@offset", flag)),
+    }
+}
+
+fn parse_path(flag: &str, value: Option<String>) -> String {
+    value.unwrap_or_else(|| usage_error(&format!("{} expects a file", flag)))
+}
+
+fn number_arg(flag: &str, value: Option<String>) -> usize {
+    let value = value.unwrap_or_else(|| usage_error(&format!("{} expects a value", flag)));
+    parse_number(&value)
+        .unwrap_or_else(|| usage_error(&format!("{}: invalid number {}", flag, value)))
+}
+
+/// Parses a list like `10,20,30` of values to push, the last ending up on
+/// top of the stack.
+fn stack_values(flag: &str, value: Option<String>) -> Vec<u16> {
+    let value = parse_path(flag, value);
+    value
+        .split(',')
+        .map(str::trim)
+        .map(|word| match parse_number(word) {
+            Some(word) if word < INTEGER_RANGE => word as u16,
+            _ => usage_error(&format!(
+                "{}: invalid value {} (expected 0..{})",
+                flag,
+                word,
+                INTEGER_RANGE - 1
+            )),
+        })
+        .collect()
+}
+
+/// Parses a list like `r7,r0` into a bitmask of register numbers.
+fn register_mask(list: &str) -> u8 {
+    let mut mask = 0;
+    for name in list.split(',').map(str::trim) {
+        match name.strip_prefix('r').and_then(|n| n.parse::<usize>().ok()) {
+            Some(reg) if reg < NUMBER_OF_REGISTERS => mask |= 1 << reg,
+            _ => usage_error(&format!(
+                "--trace-regs-filter: unknown register {} (expected r0..r7)",
+                name
+            )),
         }
-        1 | 7 | 8 | 14 | 15 | 16 => {
-            let a_raw: u16 = read_mem(&mut mach, get_addr(raw_addr + 1).unwrap());
-            let a: u16 = get_oprnd_value(&mut mach, raw_addr + 1);
-            let b: u16 = get_oprnd_value(&mut mach, raw_addr + 2);
-            match instr {
-                1 => Some(Instruction::Set(a_raw, b)),
-                7 => Some(Instruction::Jt(a, b)),
-                8 => Some(Instruction::Jf(a, b)),
-                14 => Some(Instruction::Not(a_raw, b)),
-                15 => Some(Instruction::Rmem(a_raw, b)),
-                16 => Some(Instruction::Wmem(a, b)),
-                _ => None,
-            }
+    }
+    mask
+}
+
+fn load_program(path: &str) -> Vec<u16> {
+    open_or_exit(path, load_file(path))
+}
+
+/// With `--segment`, the program is put together from those files alone
+/// and the positional path is ignored.
+fn load_segments(segments: &[(String, usize)]) -> Vec<u16> {
+    let segments: Vec<(&str, usize)> = segments
+        .iter()
+        .map(|(path, offset)| (path.as_str(), *offset))
+        .collect();
+    synacor_challenge::load_segments(&segments).unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        process::exit(1);
+    })
+}
+
+fn dump_strings_sorted(memory: &[u16]) {
+    for block in string_map(memory, MIN_STRING_LEN) {
+        let kind = match block.kind {
+            StringKind::Data => "data",
+            StringKind::Out => "out ",
+        };
+        print!(
+            "{:5}-{:5} {:5} {} {:?}",
+            block.start,
+            block.end,
+            block.word_count(),
+            kind,
+            block.text
+        );
+        if !block.refs.is_empty() {
+            let refs: Vec<String> = block.refs.iter().map(|addr| addr.to_string()).collect();
+            print!(" refs: {}", refs.join(" "));
         }
-        4 | 5 | 9 | 10 | 11 | 12 | 13 => {
-            let a_raw: u16 = read_mem(&mut mach, get_addr(raw_addr + 1).unwrap());
-            let b: u16 = get_oprnd_value(&mut mach, raw_addr + 2);
-            let c: u16 = get_oprnd_value(&mut mach, raw_addr + 3);
-            match instr {
-                4 => Some(Instruction::Eq(a_raw, b, c)),
-                5 => Some(Instruction::Gt(a_raw, b, c)),
-                9 => Some(Instruction::Add(a_raw, b, c)),
-                10 => Some(Instruction::Mult(a_raw, b, c)),
-                11 => Some(Instruction::Mod(a_raw, b, c)),
-                12 => Some(Instruction::And(a_raw, b, c)),
-                13 => Some(Instruction::Or(a_raw, b, c)),
-                _ => None,
-            }
+        println!();
+    }
+}
+
+fn decode_one(memory: &[u16], addr: usize) {
+    if addr >= MEMORY_SIZE {
+        eprintln!("address {} is outside memory (0..{})", addr, MEMORY_SIZE);
+        process::exit(1);
+    }
+    match decode_at(memory, addr) {
+        Some(instr) => println!("{}: {} (length {})", addr, instr, instr.size()),
+        None => {
+            eprintln!("{}: no valid instruction (word {})", addr, memory[addr]);
+            process::exit(1);
         }
-        _ => None,
     }
 }
 
-fn get_addr(addr: u16) -> Option<Address> {
-    if (addr as usize) < ADDRESS_RANGE {
-        Some(Address::Mem(addr as usize))
-    } else if (addr as usize) < ADDRESS_RANGE + NUMBER_OF_REGISTERS {
-        Some(Address::Reg((addr as usize) - ADDRESS_RANGE))
-    } else {
-        None
+fn functions(memory: &[u16]) {
+    for addr in find_call_targets(memory) {
+        println!("{}", addr);
     }
+    for addr in find_indirect_calls(memory) {
+        let instr = decode_at(memory, addr as usize).unwrap();
+        println!("{}: {} (target unknown)", addr, instr);
+    }
+}
+
+fn writers(memory: &[u16], target: usize) {
+    if target >= MEMORY_SIZE {
+        eprintln!("address {} is outside memory (0..{})", target, MEMORY_SIZE);
+        process::exit(1);
+    }
+    for addr in find_writers(memory, target as u16) {
+        println!("{}: {}", addr, decode_at(memory, addr).unwrap());
+    }
+    for addr in find_indirect_writes(memory) {
+        let instr = decode_at(memory, addr as usize).unwrap();
+        println!("{}: {} (target unknown)", addr, instr);
+    }
+}
+
+/// Writes the linear sweep disassembly, or with `--export-walk reachable`
+/// only the statically reachable code, as `write_decoded` records.
+#[cfg(feature = "serde")]
+fn export_decoded(memory: &[u16], path: &str, reachable: bool) {
+    use synacor_challenge::analysis::{disassemble, reachable_code, write_decoded};
+
+    let code = if reachable {
+        reachable_code(memory)
+    } else {
+        disassemble(memory)
+    };
+    let mut out = BufWriter::new(open_or_exit(path, File::create(path)));
+    open_or_exit(path, write_decoded(&code, &mut out));
 }
 
-fn comp_op(mut mach: &mut Machine, instr: Instruction) {
-    let raw_addr: u16;
-    let value: u16;
-    match instr {
-        Instruction::Eq(a, b, c) => {
-            raw_addr = a;
-            if b == c {
-                value = 1;
+#[cfg(not(feature = "serde"))]
+fn export_decoded(_memory: &[u16], _path: &str, _reachable: bool) {
+    usage_error("--export-decoded needs the serde feature");
+}
+
+fn dry_run(memory: &[u16]) {
+    match linear_decode(memory) {
+        Ok(decoded) => println!("dry run passed: {} instructions decode", decoded),
+        Err(addr) => {
+            let problem = if opcode_arity(memory[addr]).is_some() {
+                "runs past the end of memory"
             } else {
-                value = 0;
-            }
+                "is not an opcode"
+            };
+            println!(
+                "dry run failed: word {} at {} {}",
+                memory[addr], addr, problem
+            );
+            process::exit(1);
         }
-        Instruction::Gt(a, b, c) => {
-            raw_addr = a;
-            if b > c {
-                value = 1;
-            } else {
-                value = 0;
+    }
+}
+
+const MAP_BAR_WIDTH: usize = 50;
+
+fn print_memory_map(memory: &[u16]) {
+    let map = memory_map(memory);
+    let rows = [
+        ("code", map.code),
+        ("data", map.data),
+        ("unused", map.unused),
+        ("other", map.other),
+    ];
+    for (name, count) in rows {
+        let share = count as f64 / memory.len() as f64;
+        let bar = "#".repeat((share * MAP_BAR_WIDTH as f64).round() as usize);
+        println!(
+            "{:6} {:5} {:5.1}% |{:width$}|",
+            name,
+            count,
+            share * 100.0,
+            bar,
+            width = MAP_BAR_WIDTH
+        );
+    }
+}
+
+fn verify_lengths(memory: &[u16]) {
+    let found = misaligned_targets(memory);
+    for &(source, target, start) in &found {
+        println!(
+            "{}: {} targets {}, inside {}: {}",
+            source,
+            decode_at(memory, source as usize).unwrap(),
+            target,
+            start,
+            decode_at(memory, start as usize).unwrap()
+        );
+    }
+    if !found.is_empty() {
+        process::exit(1);
+    }
+}
+
+/// Pre-flight check for `--verify-bytecode`; exits if the program fails it.
+fn preflight(memory: &[u16]) {
+    let verification = verify_bytecode(memory);
+    for (addr, problem) in &verification.problems {
+        eprintln!("{}: {}", addr, problem);
+    }
+    eprintln!(
+        "bytecode verification {}: {} problems, {} jumps, calls and returns not followed",
+        if verification.problems.is_empty() {
+            "passed"
+        } else {
+            "failed"
+        },
+        verification.problems.len(),
+        verification.unresolved.len()
+    );
+    if !verification.problems.is_empty() {
+        process::exit(1);
+    }
+}
+
+/// The names from `--symbols`, if given.
+fn load_symbols(options: &Options) -> Symbols {
+    match &options.symbols {
+        Some(path) => open_or_exit(path, symbols::from_file(path)),
+        None => Symbols::new(),
+    }
+}
+
+fn write_listing(memory: &[u16], path: &str, symbols: &Symbols) {
+    let mut out = BufWriter::new(open_or_exit(path, File::create(path)));
+    let written = labeled_listing(memory, symbols)
+        .iter()
+        .try_for_each(|line| writeln!(out, "{}", line))
+        .and_then(|_| out.flush());
+    if let Err(err) = written {
+        eprintln!("{}: {}", path, err);
+        process::exit(1);
+    }
+}
+
+fn open_or_exit<T>(path: &str, opened: std::io::Result<T>) -> T {
+    opened.unwrap_or_else(|err| {
+        eprintln!("{}: {}", path, err);
+        process::exit(1);
+    })
+}
+
+/// Reads instructions from stdin one line at a time and prints the words
+/// each assembles to, until end of input.
+fn asm_repl() {
+    let stdin = io::stdin();
+    loop {
+        print!("asm> ");
+        let _ = io::stdout().flush();
+        let mut line = String::new();
+        if stdin.read_line(&mut line).unwrap_or(0) == 0 {
+            println!();
+            return;
+        }
+        match encode_line(&line) {
+            Ok(words) if words.is_empty() => {}
+            Ok(words) => {
+                let words: Vec<String> = words.iter().map(|word| word.to_string()).collect();
+                println!("{}", words.join(" "));
             }
+            Err(err) => println!("error: {}", err.message),
         }
-        _ => return,
     }
+}
+
+/// Instructions `--write-golden` records by default, all of them part of
+/// the deterministic self-test.
+const GOLDEN_STEPS: u64 = 10_000;
 
-    let addr: Address = get_addr(raw_addr).unwrap();
-    write_mem(&mut mach, addr, value);
+/// Records the first `steps` instructions, or those up to the first `in`,
+/// as an unannotated trace for `--validate-against`.
+fn write_golden(path: &str, golden: &str, steps: u64) {
+    let mut machine = Machine::new(load_program(path));
+    machine.output = Box::new(io::sink());
+    let file = BufWriter::new(open_or_exit(golden, File::create(golden)));
+    let mut tracer = Tracer::new(Box::new(file));
+    tracer.set_annotate(false);
+    machine.tracer = Some(tracer);
+
+    let mut recorded = 0;
+    while recorded < steps && !machine.needs_input() {
+        match machine.step() {
+            Ok(true) => recorded += 1,
+            Ok(false) => break,
+            Err(err) => {
+                eprintln!("{}", err);
+                process::exit(1);
+            }
+        }
+    }
+    println!("recorded {} instructions to {}", recorded, golden);
 }
 
-fn bin_op(mut mach: &mut Machine, op: fn(usize, usize) -> usize, instr: Instruction) {
-    let addr: Address;
-    let result: usize;
-    match instr {
-        Instruction::Add(a, b, c) | Instruction::Mult(a, b, c) => {
-            addr = get_addr(a).unwrap();
-            result = op(b as usize, c as usize) % INTEGER_RANGE
+/// Runs the program for as many instructions as the golden trace has lines
+/// and fails on the first that differs, so a change in the VM's behaviour
+/// on the real binary is caught. Skipped if the program is missing.
+fn validate_against(path: &str, golden: &str) {
+    if !Path::new(path).exists() {
+        println!("skipping validation: {} not found", path);
+        return;
+    }
+    let lines = open_or_exit(golden, fs::read_to_string(golden))
+        .lines()
+        .count();
+    let file = open_or_exit(golden, File::open(golden));
+    let mut comparator = TraceComparator::new(Box::new(BufReader::new(file)));
+    let mut machine = Machine::new(load_program(path));
+    machine.output = Box::new(io::sink());
+
+    let failed = |message: String| -> ! {
+        eprintln!("{}: {}", golden, message);
+        process::exit(1);
+    };
+    for _ in 0..lines {
+        let instr = get_op(&machine)
+            .unwrap_or_else(|| failed(format!("no valid instruction at ip {}", machine.ip)));
+        if let Err(message) = comparator.check(machine.ip, instr) {
+            failed(message);
         }
-        Instruction::Mod(a, b, c) | Instruction::And(a, b, c) | Instruction::Or(a, b, c) => {
-            addr = get_addr(a).unwrap();
-            result = op(b as usize, c as usize)
+        match machine.step() {
+            Ok(true) => {}
+            Ok(false) => break,
+            Err(err) => failed(err.to_string()),
         }
-        _ => return,
     }
-    write_mem(&mut mach, addr, result as u16);
+    if let Err(message) = comparator.finish() {
+        failed(message);
+    }
+    println!("validation passed: {} instructions match {}", lines, golden);
 }
 
-fn main() {
-    let file = fs::read("challenge.bin").unwrap();
-
-    let file_size = file.len() / 2;
-    let mut buffer: [u16; MEMORY_SIZE] = [0; MEMORY_SIZE];
-    let mut rdr: Cursor<Vec<u8>> = Cursor::new(file);
-    rdr.read_u16_into::<LittleEndian>(&mut buffer[0..file_size])
-        .unwrap();
-
-    let stack: &mut LinkedList<u16> = &mut LinkedList::new();
-    let mut machine: Machine = Machine {
-        memory: Box::new(buffer.to_vec()),
-        registers: Box::new(vec![0u16; NUMBER_OF_REGISTERS]),
-        stack,
-        ip: 0u16,
+/// Input is read from the `--input` script first, then answered by the
+/// `--autoplay` table, and finally typed in by the user. Autoplay answers
+/// are echoed to `echo`, the machine's output.
+fn input_source(options: &Options, echo: &Shared) -> Box<dyn InputSource> {
+    let mut source = input::default_source();
+    if let Some(path) = &options.autoplay {
+        source = Box::new(open_or_exit(
+            path,
+            Autoplay::from_file(path, source, Box::new(echo.clone())),
+        ));
+    }
+    if let Some(path) = &options.input {
+        source = Box::new(open_or_exit(path, Script::from_file(path, source)));
+    }
+    source
+}
+
+/// Stdout, slowed down by `--scroll-delay` when it is a terminal.
+fn stdout_sink(options: &Options) -> Box<dyn Write> {
+    let stdout = io::stdout();
+    if options.scroll_delay > 0 && stdout.is_terminal() {
+        let delay = Duration::from_millis(options.scroll_delay);
+        Box::new(ScrollDelay::new(Box::new(stdout), delay))
+    } else {
+        Box::new(stdout)
+    }
+}
+
+/// Lines of output kept for the debugger's `output` command unless
+/// `--scrollback` says otherwise.
+const DEFAULT_SCROLLBACK: usize = 1000;
+
+/// `--scroll-delay` only slows down a terminal; piped output is written at
+/// full speed. `--tee` copies the raw output bytes to a file, with line
+/// endings as `--transcript-newline` says, and `--collect-codes` lists the
+/// challenge codes printed once the run ends. `--utf8-output` writes the
+/// output as text instead of raw bytes.
+fn output_sink(options: &Options) -> Box<dyn Write> {
+    let mut sink = stdout_sink(options);
+    if options.utf8_output {
+        sink = Box::new(Utf8Output::new(sink));
+    }
+    if let Some(path) = &options.tee {
+        let file = BufWriter::new(open_or_exit(path, File::create(path)));
+        let copy = LineEndings::new(Box::new(file), options.transcript_newline);
+        sink = Box::new(Tee::new(sink, Box::new(copy)));
+    }
+    if options.collect_codes {
+        sink = Box::new(CodeCollector::new(sink));
+    }
+    sink
+}
+
+/// Tracing goes to stderr unless `--trace-file` is given; either that or
+/// `--trace-filter` turns it on without `--trace`. `--show-exec` instead
+/// shows each instruction on stdout, interleaved with the program's output.
+fn tracer(options: &Options, symbols: &Symbols) -> Option<Tracer> {
+    let mut tracer = if options.show_exec {
+        if options.trace || options.trace_file.is_some() {
+            usage_error("--show-exec cannot be combined with --trace or --trace-file");
+        }
+        let mut tracer = Tracer::new(stdout_sink(options));
+        tracer.set_annotate(false);
+        tracer
+    } else if options.trace || options.trace_file.is_some() || options.trace_filter.is_some() {
+        let out: Box<dyn Write> = match &options.trace_file {
+            Some(path) => Box::new(BufWriter::new(open_or_exit(path, File::create(path)))),
+            None => Box::new(io::stderr()),
+        };
+        Tracer::new(out)
+    } else {
+        return None;
     };
+    if let Some(filter) = &options.trace_filter {
+        if let Err(name) = tracer.set_filter(filter.split(',').map(str::trim)) {
+            usage_error(&format!(
+                "--trace-filter: unknown opcode {} (expected one of {})",
+                name,
+                OPCODE_NAMES.join(",")
+            ));
+        }
+    }
+    tracer.set_symbols(symbols.clone());
+    Some(tracer)
+}
+
+const CONTEXT_WORDS: usize = 4;
 
-    let mut input_iter: IntoIter<u8> = vec![].into_iter();
+/// The instruction at the failing `ip`, the raw words starting there and the
+/// instructions that led to it, for error reports.
+fn error_context(machine: &Machine, ip: u16, symbols: &Symbols) -> String {
+    let memory = &machine.memory;
+    let start = (ip as usize).min(memory.len());
+    let end = (start + CONTEXT_WORDS).min(memory.len());
+    let words: Vec<String> = memory[start..end].iter().map(|w| w.to_string()).collect();
+    let mut context = format!(
+        "{}\n       words: {}\nrecent instructions:",
+        listing(memory, ip, 1, None, symbols).join("\n"),
+        words.join(" ")
+    );
+    for addr in machine.ip_history() {
+        context.push('\n');
+        context.push_str(&listing(memory, addr, 1, None, symbols).join("\n"));
+    }
+    context
+}
 
+/// Runs the machine while checking every instruction against a baseline
+/// trace, exiting at the first divergence.
+fn run_compared(machine: &mut Machine, path: &str) -> Result<(), VmError> {
+    let file = open_or_exit(path, File::open(path));
+    let mut comparator = TraceComparator::new(Box::new(BufReader::new(file)));
+    let diverged = |message: String| -> ! {
+        eprintln!("{}: {}", path, message);
+        process::exit(1);
+    };
     loop {
-        let instr: Instruction = get_op(&mut machine).unwrap();
-
-        match instr {
-            Instruction::Halt => break,
-            Instruction::Set(a, b) => {
-                let addr: Address = get_addr(a).unwrap();
-                match addr {
-                    Address::Reg(_) => {
-                        write_mem(&mut machine, addr, b);
-                        machine.ip += 3;
-                    }
-                    _ => {
-                        println!("Set operand is not an argument");
-                    }
-                }
-            }
-            Instruction::Push(a) => {
-                machine.stack.push_front(a);
-                machine.ip += 2;
-            }
-            Instruction::Pop(a) => {
-                let address = get_addr(a).unwrap();
-                let value = machine.stack.pop_front().unwrap();
-                write_mem(&mut machine, address, value);
-                machine.ip += 2;
-            }
-            Instruction::Eq(_, _, _) | Instruction::Gt(_, _, _) => {
-                comp_op(&mut machine, instr);
-                machine.ip += 4;
-            }
-            Instruction::Jmp(a) => machine.ip = a,
-            Instruction::Jt(a, b) => {
-                if a != 0 {
-                    machine.ip = b;
-                } else {
-                    machine.ip += 3;
-                }
-            }
-            Instruction::Jf(a, b) => {
-                if a == 0 {
-                    machine.ip = b;
-                } else {
-                    machine.ip += 3;
-                }
-            }
-            Instruction::Add(_, _, _) => {
-                bin_op(&mut machine, |x, y| x + y, instr);
-                machine.ip += 4;
-            }
-            Instruction::Mult(_, _, _) => {
-                bin_op(&mut machine, |x, y| x * y, instr);
-                machine.ip += 4;
-            }
-            Instruction::Mod(_, _, _) => {
-                bin_op(&mut machine, |x, y| x % y, instr);
-                machine.ip += 4;
-            }
-            Instruction::And(_, _, _) => {
-                bin_op(&mut machine, |x, y| x & y, instr);
-                machine.ip += 4;
+        if let Some(instr) = get_op(machine) {
+            if let Err(message) = comparator.check(machine.ip, instr) {
+                diverged(message);
             }
-            Instruction::Or(_, _, _) => {
-                bin_op(&mut machine, |x, y| x | y, instr);
-                machine.ip += 4;
-            }
-            Instruction::Not(a, b) => {
-                let addr: Address = get_addr(a).unwrap();
-                let value: u16 = b ^ 0x7FFFu16;
-                write_mem(&mut machine, addr, value);
-                machine.ip += 3;
-            }
-            Instruction::Rmem(a, b) => {
-                let addr_a: Address = get_addr(a).unwrap();
-                let addr_b: Address = get_addr(b).unwrap();
-                let value: u16 = read_mem(&mut machine, addr_b);
-                write_mem(&mut machine, addr_a, value);
-                machine.ip += 3;
-            }
-            Instruction::Wmem(a, b) => {
-                let addr: Address = get_addr(a).unwrap();
-                write_mem(&mut machine, addr, b);
-                machine.ip += 3;
-            }
-            Instruction::Call(a) => {
-                machine.stack.push_front(machine.ip + 2);
-                machine.ip = a;
+        }
+        if !machine.step()? {
+            break;
+        }
+    }
+    if let Err(message) = comparator.finish() {
+        diverged(message);
+    }
+    Ok(())
+}
+
+/// Runs the debugger. With `--breakpoints`, the breakpoints in the file are
+/// set first, if it exists, and whatever is set at the end is saved back.
+fn debug_session(
+    debugger: &mut Debugger,
+    machine: &mut Machine,
+    breakpoints: Option<&str>,
+) -> Result<(), VmError> {
+    if let Some(path) = breakpoints.filter(|path| Path::new(path).exists()) {
+        open_or_exit(path, debugger.load_breakpoints(machine, path));
+    }
+    let result = debugger.repl(machine);
+    if let Some(path) = breakpoints {
+        if let Err(err) = debugger.save_breakpoints(path) {
+            eprintln!("{}: {}", path, err);
+        }
+    }
+    result
+}
+
+/// Runs headless on the `--input` script alone until it is used up or the
+/// program halts, and returns everything printed.
+fn run_script(machine: &mut Machine, path: &str) -> Result<Vec<u8>, VmError> {
+    let mut script = open_or_exit(path, fs::read(path));
+    if !script.is_empty() && !script.ends_with(b"\n") {
+        script.push(b'\n');
+    }
+    let capture = Capture::new();
+    machine.output = Box::new(capture.clone());
+    machine.push_input(&script);
+    machine.run_until_input()?;
+    Ok(capture.bytes())
+}
+
+/// Runs `run_script` and reports each `--expect` text as found in the
+/// output or not, exiting with 1 unless all were.
+fn run_expecting(machine: &mut Machine, path: &str, expected: &[String]) -> Result<(), VmError> {
+    let output = String::from_utf8_lossy(&run_script(machine, path)?).into_owned();
+    let mut matched = true;
+    for text in expected {
+        let found = output.contains(text.as_str());
+        println!("{} {:?}", if found { "found" } else { "missing" }, text);
+        matched &= found;
+    }
+    if !matched {
+        process::exit(1);
+    }
+    Ok(())
+}
+
+/// Feeds the `in` bytes of an `--event-log` recording to the program, then
+/// carries on with the usual input. With `strict`, each byte is held back
+/// until execution reaches the `in` at the ip it was read at, with exactly
+/// the recorded output printed since the previous one; the first point
+/// where the run diverges is reported and exits with 1.
+fn replay_events(machine: &mut Machine, path: &str, strict: bool) -> Result<(), VmError> {
+    let text = open_or_exit(path, fs::read_to_string(path));
+    let events = match events::parse(&text) {
+        Ok(events) => events,
+        Err(problem) => {
+            eprintln!("{}: {}", path, problem);
+            process::exit(1);
+        }
+    };
+    if !strict {
+        let bytes: Vec<u8> = events
+            .iter()
+            .filter(|event| event.kind == EventKind::In)
+            .map(|event| event.byte)
+            .collect();
+        machine.push_input(&bytes);
+        return machine.run();
+    }
+    machine.nonblocking_input = true;
+    let mut printed = Vec::new();
+    for (number, event) in events.iter().enumerate() {
+        if event.kind == EventKind::Out {
+            printed.push(event.byte);
+            continue;
+        }
+        match machine.run() {
+            Err(VmError::NeedsInput { ip }) if ip == event.ip => {}
+            Err(VmError::NeedsInput { ip }) => {
+                println!(
+                    "replay diverged at event {}: input read at ip {}, recorded at {}",
+                    number + 1,
+                    ip,
+                    event.ip
+                );
+                process::exit(1);
             }
-            Instruction::Ret => {
-                let value = machine.stack.pop_front().unwrap();
-                machine.ip = value;
+            Ok(()) => {
+                println!(
+                    "replay diverged at event {}: halted before input at ip {}",
+                    number + 1,
+                    event.ip
+                );
+                process::exit(1);
             }
-            Instruction::Out(a) => {
-                print!("{}", (a as u8) as char);
-                machine.ip += 2;
+            Err(err) => return Err(err),
+        }
+        if machine.output_since_input() != String::from_utf8_lossy(&printed) {
+            println!(
+                "replay diverged at event {}: output before input at ip {} differs",
+                number + 1,
+                event.ip
+            );
+            process::exit(1);
+        }
+        machine.push_input(&[event.byte]);
+        printed.clear();
+    }
+    machine.nonblocking_input = false;
+    machine.run()
+}
+
+const REPLAY_CONTEXT: usize = 40;
+
+/// Runs `run_script` and compares the output byte for byte with the
+/// transcript at `expected_path`, e.g. one recorded with `--tee`. The first
+/// difference is shown with the text around it, and exits with 1.
+fn verify_replay(machine: &mut Machine, path: &str, expected_path: &str) -> Result<(), VmError> {
+    let expected = open_or_exit(expected_path, fs::read(expected_path));
+    let actual = run_script(machine, path)?;
+    let offset = match expected.iter().zip(&actual).position(|(e, a)| e != a) {
+        Some(offset) => offset,
+        None if expected.len() == actual.len() => {
+            println!("replay matches {} ({} bytes)", expected_path, actual.len());
+            return Ok(());
+        }
+        None => expected.len().min(actual.len()),
+    };
+    let context = |bytes: &[u8]| {
+        let start = offset.saturating_sub(REPLAY_CONTEXT);
+        let end = (offset + REPLAY_CONTEXT).min(bytes.len());
+        let text = |range: &[u8]| format!("{:?}", String::from_utf8_lossy(range));
+        format!(
+            "{} >>> {}",
+            text(&bytes[start..offset]),
+            text(&bytes[offset..end])
+        )
+    };
+    println!(
+        "replay differs from {} at byte {} (expected {} bytes, got {})",
+        expected_path,
+        offset,
+        expected.len(),
+        actual.len()
+    );
+    println!("  expected: {}", context(&expected));
+    println!("  actual:   {}", context(&actual));
+    process::exit(1);
+}
+
+/// One `opcode,count` row per opcode and a total.
+fn write_histogram<W: Write>(counts: &[u64], out: &mut W) -> io::Result<()> {
+    writeln!(out, "opcode,count")?;
+    for (name, count) in OPCODE_NAMES.iter().zip(counts) {
+        writeln!(out, "{},{}", name, count)?;
+    }
+    writeln!(out, "total,{}", counts.iter().sum::<u64>())?;
+    out.flush()
+}
+
+/// Lists on stderr the instructions executed in each subroutine, with and
+/// without its callees, busiest first.
+fn print_hot_functions<F: Fn(u16) -> String>(profile: &CallProfile, name: F) {
+    eprintln!(
+        "{:>5}  {:>12}  {:>12}  name",
+        "entry", "inclusive", "exclusive"
+    );
+    for (entry, inclusive, exclusive) in profile.functions() {
+        eprintln!(
+            "{:5}  {:12}  {:12}  {}",
+            entry,
+            inclusive,
+            exclusive,
+            name(entry)
+        );
+    }
+}
+
+/// Executes `steps` instructions, or fewer if the program halts first, and
+/// writes ip, registers and the nonzero memory to `path` in the
+/// `--init-state` format, with the stack as a comment.
+fn step_dump(machine: &mut Machine, steps: u64, path: &str) -> Result<(), VmError> {
+    let mut executed = 0;
+    while executed < steps && machine.step()? {
+        executed += 1;
+    }
+    let note = if executed < steps {
+        format!("halted after {} of {} instructions", executed, steps)
+    } else {
+        format!("after {} instructions", executed)
+    };
+    let stack: Vec<String> = machine
+        .stack_snapshot()
+        .iter()
+        .map(u16::to_string)
+        .collect();
+    let written = File::create(path).and_then(|file| {
+        let mut out = BufWriter::new(file);
+        writeln!(out, "# {}", note)?;
+        writeln!(out, "# stack, top first: {}", stack.join(" "))?;
+        writeln!(out, "ip = {}\n\n[registers]", machine.ip)?;
+        for (register, value) in machine.registers.iter().enumerate() {
+            writeln!(out, "r{} = {}", register, value)?;
+        }
+        writeln!(out, "\n[memory]")?;
+        for (addr, &word) in machine.memory.iter().enumerate() {
+            if word != 0 {
+                writeln!(out, "{} = {}", addr, word)?;
             }
-            Instruction::In(a) => {
-                if input_iter.len() == 0 {
-                    let mut input = String::new();
-                    stdin()
-                        .read_line(&mut input)
-                        .expect("Did not enter a correct string");
-
-                    input_iter = input.into_bytes().into_iter();
+        }
+        out.flush()
+    });
+    open_or_exit(path, written);
+    eprintln!("{}, state written to {}", note, path);
+    Ok(())
+}
+
+/// Fills the registers with numbers from a splitmix64 sequence started at
+/// `seed`, so a program that reads a register before setting it behaves
+/// differently than with the zeroes the spec starts with.
+fn randomize_registers(machine: &mut Machine, seed: u64) {
+    let mut state = seed;
+    for register in machine.registers.iter_mut() {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        *register = ((z ^ (z >> 31)) % INTEGER_RANGE as u64) as u16;
+    }
+    let registers: Vec<String> = machine.registers.iter().map(u16::to_string).collect();
+    eprintln!("random registers (seed {}): {}", seed, registers.join(" "));
+}
+
+/// Saves a snapshot to `path`, first moving the previous ones along to
+/// `path.1` up to `path.<keep - 1>`. The snapshot is written next to `path`
+/// and renamed into place so a crash never leaves a torn autosave. Failures
+/// are reported but do not stop the game.
+fn autosave(machine: &Machine, path: &str, keep: usize) {
+    let rotated = |n: usize| match n {
+        0 => path.to_string(),
+        n => format!("{}.{}", path, n),
+    };
+    for n in (1..keep).rev() {
+        if Path::new(&rotated(n - 1)).exists() {
+            let _ = fs::rename(rotated(n - 1), rotated(n));
+        }
+    }
+    let partial = format!("{}.partial", path);
+    let saved = File::create(&partial)
+        .and_then(|file| machine.save_snapshot(&mut BufWriter::new(file)))
+        .and_then(|_| fs::rename(&partial, path));
+    if let Err(err) = saved {
+        eprintln!("autosave: {}: {}", path, err);
+    }
+}
+
+fn run(options: &Options, memory: Vec<u16>) {
+    if options.replay_events_strict && options.replay_events.is_none() {
+        usage_error("--replay-events-strict needs --replay-events");
+    }
+    if options.verify_bytecode {
+        preflight(&memory);
+    }
+    let symbols = load_symbols(options);
+    let mut machine = Machine::new(memory);
+    if let Some(seed) = options.random_registers {
+        randomize_registers(&mut machine, seed);
+    }
+    if let Some(path) = &options.init_state {
+        open_or_exit(path, Preset::from_file(path)).apply(&mut machine);
+    }
+    if let Some(path) = &options.cpu_state {
+        let file = open_or_exit(path, File::open(path));
+        open_or_exit(path, machine.load_cpu_state(&mut BufReader::new(file)));
+    }
+    if let Some(path) = &options.restore {
+        let file = open_or_exit(path, File::open(path));
+        open_or_exit(path, machine.load_snapshot(&mut BufReader::new(file)));
+    }
+    machine.seed_stack(&options.seed_stack);
+    let mut sink = output_sink(options);
+    let scrollback = Scrollback::new(options.scrollback);
+    if options.debug {
+        sink = Box::new(Tee::new(sink, Box::new(scrollback.clone())));
+    }
+    let output = Shared::new(sink);
+    machine.input = input_source(options, &output);
+    machine.output = Box::new(output);
+    machine.warn_overflow = options.warn_overflow;
+    machine.warn_unbalanced_stack = options.warn_unbalanced_stack;
+    machine.max_stack_depth = options.max_stack_depth;
+    machine.max_memory_writes = options.max_memory_writes;
+    if options.skip_illegal {
+        machine.on_illegal_opcode = IllegalOpcodePolicy::SkipWord;
+    }
+    machine.strict = options.strict;
+    machine.ascii_output = options.utf8_output;
+    machine.tracer = tracer(options, &symbols);
+    machine.trace_registers = options.trace_regs;
+    if let Some(path) = &options.registers_csv {
+        let file = BufWriter::new(open_or_exit(path, File::create(path)));
+        machine.register_csv = Some(RegisterCsv::new(Box::new(file)));
+    }
+    if let Some(path) = &options.event_log {
+        let file = BufWriter::new(open_or_exit(path, File::create(path)));
+        machine.event_log = Some(EventLog::new(Box::new(file)));
+    }
+    machine.count_opcodes(options.opcode_histogram.is_some());
+    if options.flamegraph.is_some() || options.hot_functions {
+        machine.profile = Some(CallProfile::new());
+    }
+    if let Some(path) = options.autosave.clone() {
+        let keep = options.autosave_keep;
+        machine.set_input_hook(Box::new(move |machine| autosave(machine, &path, keep)));
+    }
+    if options.hints {
+        let mut shown = false;
+        machine.set_heartbeat(
+            1,
+            Box::new(move |machine| {
+                if let Some(hint) = teleporter::hint(machine).filter(|_| !shown) {
+                    eprintln!("hint: {}", hint);
+                    shown = true;
                 }
+            }),
+        );
+    }
 
-                write_mem(
-                    &mut machine,
-                    get_addr(a).unwrap(),
-                    input_iter.next().unwrap() as u16,
-                );
+    let result = if options.debug {
+        let mut debugger = Debugger::new();
+        debugger.pause_on_halt = options.pause_on_halt;
+        debugger.symbols = symbols.clone();
+        debugger.scrollback = Some(scrollback);
+        if options.segments.is_empty() {
+            debugger.program = Some(options.path.clone());
+        }
+        debug_session(&mut debugger, &mut machine, options.breakpoints.as_deref())
+    } else if let Some((steps, path)) = &options.step_dump {
+        step_dump(&mut machine, *steps, path)
+    } else if let Some(path) = &options.replay_events {
+        replay_events(&mut machine, path, options.replay_events_strict)
+    } else if let Some(expected) = &options.verify_replay {
+        match &options.input {
+            Some(path) => verify_replay(&mut machine, path, expected),
+            None => usage_error("--verify-replay needs --input"),
+        }
+    } else if !options.expect.is_empty() {
+        match &options.input {
+            Some(path) => run_expecting(&mut machine, path, &options.expect),
+            None => usage_error("--expect needs --input"),
+        }
+    } else if let Some(path) = &options.compare_trace {
+        run_compared(&mut machine, path)
+    } else if options.safe {
+        machine.run_safe()
+    } else {
+        machine.run()
+    };
+    if result.is_ok() && options.pause_on_halt && !options.debug {
+        let _ = machine.output.flush();
+        println!("\nhalted");
+        debugger::show_state(&machine, &symbols);
+        print!("press enter to exit");
+        let _ = io::stdout().flush();
+        let _ = io::stdin().read_line(&mut String::new());
+    }
+    if let (Some(path), Some(counts)) = (&options.opcode_histogram, machine.opcode_counts()) {
+        let written =
+            File::create(path).and_then(|file| write_histogram(counts, &mut BufWriter::new(file)));
+        if let Err(err) = written {
+            eprintln!("{}: {}", path, err);
+        }
+    }
+    // Subroutines are named as in `--listing`, or by `--symbols`.
+    let name = |entry: u16| match symbols.get(&entry) {
+        Some(label) => label.clone(),
+        None => format!("sub_{:04x}", entry),
+    };
+    if let (true, Some(profile)) = (options.hot_functions, &machine.profile) {
+        print_hot_functions(profile, name);
+    }
+    if let (Some(path), Some(profile)) = (&options.flamegraph, &machine.profile) {
+        let written =
+            File::create(path).and_then(|file| profile.write(&mut BufWriter::new(file), name));
+        if let Err(err) = written {
+            eprintln!("{}: {}", path, err);
+        }
+    }
+    if let Some(path) = &options.dump_memory {
+        let written = File::create(path)
+            .and_then(|file| dump_memory(&machine.memory, &mut BufWriter::new(file)));
+        if let Err(err) = written {
+            eprintln!("{}: {}", path, err);
+        }
+    }
+    let context = result
+        .as_ref()
+        .err()
+        .and_then(VmError::ip)
+        .map(|ip| error_context(&machine, ip, &symbols));
+    drop(machine);
+    if let Err(err) = result {
+        eprintln!("{}", err);
+        if let Some(context) = context {
+            eprintln!("{}", context);
+        }
+        process::exit(1);
+    }
+}
 
-                machine.ip += 2;
-            }
-            Instruction::Noop => machine.ip += 1,
+fn main() {
+    let options = parse_args();
+    match options.mode {
+        Mode::AsmRepl => return asm_repl(),
+        Mode::WriteGolden(ref golden) => {
+            return write_golden(&options.path, golden, options.golden_steps)
+        }
+        Mode::ValidateAgainst(ref golden) => return validate_against(&options.path, golden),
+        _ => {}
+    }
+    let memory = if !options.segments.is_empty() {
+        load_segments(&options.segments)
+    } else if options.truncate {
+        open_or_exit(&options.path, load_file_truncated(&options.path))
+    } else {
+        load_program(&options.path)
+    };
+
+    match options.mode {
+        Mode::Run => run(&options, memory),
+        Mode::DumpStringsSorted => dump_strings_sorted(&memory),
+        Mode::DecodeOne(addr) => decode_one(&memory, addr),
+        Mode::Functions => functions(&memory),
+        Mode::Writers(addr) => writers(&memory, addr),
+        Mode::VerifyLengths => verify_lengths(&memory),
+        Mode::MemoryMap => print_memory_map(&memory),
+        Mode::DryRun => dry_run(&memory),
+        Mode::ExportDecoded(ref path) => export_decoded(&memory, path, options.reachable_walk),
+        Mode::FindInput => match find_first_input(&memory) {
+            Some(addr) => println!("{}: {}", addr, decode_at(&memory, addr as usize).unwrap()),
+            None => println!("no in instruction is statically reachable"),
+        },
+        Mode::Listing(ref path) => write_listing(&memory, path, &load_symbols(&options)),
+        Mode::Report(ref path) => {
+            let symbols = load_symbols(&options);
+            let file = open_or_exit(path, File::create(path));
+            open_or_exit(
+                path,
+                write_report(&memory, &symbols, &mut BufWriter::new(file)),
+            );
+        }
+        Mode::AsmRepl | Mode::WriteGolden(_) | Mode::ValidateAgainst(_) => {
+            unreachable!()
         }
     }
 }
@@ -0,0 +1,360 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::rc::Rc;
+use std::thread;
+use std::time::Duration;
+
+/// Pauses after every line written, so long stretches of text scroll by at a
+/// readable pace. The bytes are passed through unchanged.
+pub struct ScrollDelay {
+    inner: Box<dyn Write>,
+    delay: Duration,
+}
+
+impl ScrollDelay {
+    pub fn new(inner: Box<dyn Write>, delay: Duration) -> ScrollDelay {
+        ScrollDelay { inner, delay }
+    }
+}
+
+impl Write for ScrollDelay {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        if buf[..written].contains(&b'\n') {
+            self.inner.flush()?;
+            thread::sleep(self.delay);
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Copies everything written to a second writer, typically a transcript
+/// file. The copy is flushed after every line so a crash loses at most the
+/// line in progress.
+pub struct Tee {
+    inner: Box<dyn Write>,
+    copy: Box<dyn Write>,
+}
+
+impl Tee {
+    pub fn new(inner: Box<dyn Write>, copy: Box<dyn Write>) -> Tee {
+        Tee { inner, copy }
+    }
+}
+
+impl Write for Tee {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.copy.write_all(&buf[..written])?;
+        if buf[..written].contains(&b'\n') {
+            self.copy.flush()?;
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()?;
+        self.copy.flush()
+    }
+}
+
+/// Writes output as text: every byte must be ASCII and is turned into the
+/// character with that code point, and the resulting `String` is written,
+/// so locale handling of raw bytes on the way to a terminal cannot mangle
+/// it. Anything else fails with `InvalidData`.
+pub struct Utf8Output {
+    inner: Box<dyn Write>,
+}
+
+impl Utf8Output {
+    pub fn new(inner: Box<dyn Write>) -> Utf8Output {
+        Utf8Output { inner }
+    }
+}
+
+impl Write for Utf8Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let text = buf
+            .iter()
+            .map(|&byte| match byte {
+                0..=127 => Ok(char::from(byte)),
+                _ => Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("output byte {} is not ASCII", byte),
+                )),
+            })
+            .collect::<io::Result<String>>()?;
+        self.inner.write_all(text.as_bytes())?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// How line endings are written to a transcript.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Newline {
+    /// Exactly the bytes printed.
+    Raw,
+    /// Carriage returns dropped, so lines end in a bare LF.
+    Lf,
+    /// Every LF written as CR LF, and carriage returns printed by the
+    /// program dropped so none are doubled.
+    Crlf,
+}
+
+/// Rewrites the line endings of everything written as `newline` says.
+pub struct LineEndings {
+    inner: Box<dyn Write>,
+    newline: Newline,
+}
+
+impl LineEndings {
+    pub fn new(inner: Box<dyn Write>, newline: Newline) -> LineEndings {
+        LineEndings { inner, newline }
+    }
+}
+
+impl Write for LineEndings {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.newline == Newline::Raw {
+            return self.inner.write(buf);
+        }
+        let mut translated = Vec::with_capacity(buf.len());
+        for &byte in buf {
+            match (byte, self.newline) {
+                (b'\r', _) => {}
+                (b'\n', Newline::Crlf) => translated.extend_from_slice(b"\r\n"),
+                _ => translated.push(byte),
+            }
+        }
+        self.inner.write_all(&translated)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Keeps everything written in memory, readable through any clone while
+/// another clone is boxed up as the machine's output.
+#[derive(Clone, Default)]
+pub struct Capture {
+    bytes: Rc<RefCell<Vec<u8>>>,
+}
+
+impl Capture {
+    pub fn new() -> Capture {
+        Capture::default()
+    }
+
+    pub fn bytes(&self) -> Vec<u8> {
+        self.bytes.borrow().clone()
+    }
+}
+
+impl Write for Capture {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.bytes.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// One writer shared by several owners, e.g. the machine's output and an
+/// input source echoing the answers it gives, so both go through the same
+/// `Tee` and line handling.
+#[derive(Clone)]
+pub struct Shared {
+    inner: Rc<RefCell<Box<dyn Write>>>,
+}
+
+impl Shared {
+    pub fn new(inner: Box<dyn Write>) -> Shared {
+        Shared {
+            inner: Rc::new(RefCell::new(inner)),
+        }
+    }
+}
+
+impl Write for Shared {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.borrow_mut().flush()
+    }
+}
+
+/// Keeps the last `capacity` lines written, dropping the oldest, so a
+/// display of recent output stays bounded however much is printed. The
+/// line in progress is kept separately until its newline arrives. Like
+/// `Capture`, any clone reads what another clone in the machine's output
+/// was given.
+#[derive(Clone)]
+pub struct Scrollback {
+    state: Rc<RefCell<ScrollbackState>>,
+    capacity: usize,
+}
+
+struct ScrollbackState {
+    lines: VecDeque<String>,
+    partial: Vec<u8>,
+}
+
+impl Scrollback {
+    pub fn new(capacity: usize) -> Scrollback {
+        Scrollback {
+            state: Rc::new(RefCell::new(ScrollbackState {
+                lines: VecDeque::with_capacity(capacity),
+                partial: Vec::new(),
+            })),
+            capacity,
+        }
+    }
+
+    /// Completed lines, oldest first, without their newlines.
+    pub fn lines(&self) -> Vec<String> {
+        self.state.borrow().lines.iter().cloned().collect()
+    }
+
+    /// The line being written, not ended by a newline yet.
+    pub fn partial(&self) -> String {
+        String::from_utf8_lossy(&self.state.borrow().partial).into_owned()
+    }
+}
+
+impl Write for Scrollback {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut state = self.state.borrow_mut();
+        for &byte in buf {
+            if byte != b'\n' {
+                state.partial.push(byte);
+                continue;
+            }
+            let line = String::from_utf8_lossy(&state.partial).into_owned();
+            state.partial.clear();
+            if self.capacity == 0 {
+                continue;
+            }
+            if state.lines.len() == self.capacity {
+                state.lines.pop_front();
+            }
+            state.lines.push_back(line);
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+pub const CODE_LEN: usize = 12;
+
+/// Whether `token` looks like a challenge code: exactly `CODE_LEN` ASCII
+/// letters and digits, with both upper and lower case letters so that
+/// ordinary words do not match, e.g. `awVvRKcqPRnY`.
+pub fn is_code(token: &[u8]) -> bool {
+    token.len() == CODE_LEN
+        && token.iter().all(u8::is_ascii_alphanumeric)
+        && token.iter().any(u8::is_ascii_uppercase)
+        && token.iter().any(u8::is_ascii_lowercase)
+}
+
+/// Passes output through while picking out everything that `is_code`, and
+/// lists the codes seen, in order and without repeats, on stderr when
+/// dropped. Dropping also happens when the run ends in a panic, so the
+/// summary is not lost.
+pub struct CodeCollector {
+    inner: Box<dyn Write>,
+    token: Vec<u8>,
+    codes: Vec<String>,
+}
+
+impl CodeCollector {
+    pub fn new(inner: Box<dyn Write>) -> CodeCollector {
+        CodeCollector {
+            inner,
+            token: Vec::new(),
+            codes: Vec::new(),
+        }
+    }
+
+    fn end_token(&mut self) {
+        if is_code(&self.token) {
+            let code = String::from_utf8_lossy(&self.token).into_owned();
+            if !self.codes.contains(&code) {
+                self.codes.push(code);
+            }
+        }
+        self.token.clear();
+    }
+}
+
+impl Write for CodeCollector {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        for &byte in &buf[..written] {
+            if byte.is_ascii_alphanumeric() {
+                self.token.push(byte);
+            } else {
+                self.end_token();
+            }
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl Drop for CodeCollector {
+    fn drop(&mut self) {
+        self.end_token();
+        let _ = self.inner.flush();
+        eprintln!("codes seen: {}", self.codes.len());
+        for code in &self.codes {
+            eprintln!("  {}", code);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scrollback_drops_the_oldest_lines() {
+        let scrollback = Scrollback::new(2);
+        let mut output = scrollback.clone();
+        output.write_all(b"one\ntwo\nthree\nfou").unwrap();
+        assert_eq!(scrollback.lines(), ["two", "three"]);
+        assert_eq!(scrollback.partial(), "fou");
+
+        output.write_all(b"r\n").unwrap();
+        assert_eq!(scrollback.lines(), ["three", "four"]);
+        assert_eq!(scrollback.partial(), "");
+    }
+
+    #[test]
+    fn scrollback_without_capacity_keeps_only_the_partial_line() {
+        let mut scrollback = Scrollback::new(0);
+        scrollback.write_all(b"one\ntw").unwrap();
+        assert!(scrollback.lines().is_empty());
+        assert_eq!(scrollback.partial(), "tw");
+    }
+}
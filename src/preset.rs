@@ -0,0 +1,116 @@
+use crate::{parse_number, Machine, INTEGER_RANGE, MEMORY_SIZE, NUMBER_OF_REGISTERS};
+use std::fs;
+use std::io;
+
+/// Initial state applied on top of a freshly loaded program, written in a
+/// small subset of TOML:
+///
+/// ```text
+/// ip = 5489
+///
+/// [registers]
+/// r7 = 25734
+///
+/// [memory]
+/// 5489 = 21      # addresses and values may also be 0x hex
+/// ```
+///
+/// Anything not mentioned keeps its loaded value.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Preset {
+    pub ip: Option<u16>,
+    pub registers: Vec<(usize, u16)>,
+    pub memory: Vec<(usize, u16)>,
+}
+
+enum Section {
+    Top,
+    Registers,
+    Memory,
+}
+
+impl Preset {
+    /// Parses `text`, failing on the first line with an unknown key, an
+    /// index out of range or a value that does not fit.
+    pub fn parse(text: &str) -> Result<Preset, String> {
+        let mut preset = Preset::default();
+        let mut section = Section::Top;
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            let fail = |problem: String| Err(format!("line {}: {}", number + 1, problem));
+            if line.is_empty() {
+                continue;
+            }
+            match line {
+                "[registers]" => {
+                    section = Section::Registers;
+                    continue;
+                }
+                "[memory]" => {
+                    section = Section::Memory;
+                    continue;
+                }
+                _ if line.starts_with('[') => return fail(format!("unknown section {}", line)),
+                _ => {}
+            }
+            let (key, value) = match line.split_once('=') {
+                Some((key, value)) => (key.trim(), value.trim()),
+                None => return fail(String::from("expected `key = value`")),
+            };
+            let value = match parse_number(value) {
+                Some(value) => value,
+                None => return fail(format!("invalid number {}", value)),
+            };
+            match section {
+                Section::Top if key == "ip" => {
+                    if value >= MEMORY_SIZE {
+                        return fail(format!("ip {} is outside memory", value));
+                    }
+                    preset.ip = Some(value as u16);
+                }
+                Section::Top => return fail(format!("unknown key {}", key)),
+                Section::Registers => {
+                    let register = key
+                        .strip_prefix('r')
+                        .and_then(|n| n.parse::<usize>().ok())
+                        .filter(|&n| n < NUMBER_OF_REGISTERS);
+                    match register {
+                        Some(_) if value >= INTEGER_RANGE => {
+                            return fail(format!("{} = {} does not fit in 15 bits", key, value))
+                        }
+                        Some(register) => preset.registers.push((register, value as u16)),
+                        None => return fail(format!("no such register {}", key)),
+                    }
+                }
+                Section::Memory => match parse_number(key) {
+                    Some(addr) if addr >= MEMORY_SIZE => {
+                        return fail(format!("address {} is outside memory", key))
+                    }
+                    Some(_) if value > u16::MAX as usize => {
+                        return fail(format!("{} = {} does not fit in a word", key, value))
+                    }
+                    Some(addr) => preset.memory.push((addr, value as u16)),
+                    None => return fail(format!("invalid address {}", key)),
+                },
+            }
+        }
+        Ok(preset)
+    }
+
+    pub fn from_file(path: &str) -> io::Result<Preset> {
+        Preset::parse(&fs::read_to_string(path)?)
+            .map_err(|problem| io::Error::new(io::ErrorKind::InvalidData, problem))
+    }
+
+    pub fn apply(&self, machine: &mut Machine) {
+        if let Some(ip) = self.ip {
+            machine.ip = ip;
+        }
+        for &(register, value) in &self.registers {
+            machine.registers[register] = value;
+        }
+        for &(addr, value) in &self.memory {
+            machine.memory[addr] = value;
+        }
+    }
+}
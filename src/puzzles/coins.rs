@@ -0,0 +1,95 @@
+use super::SolverError;
+
+pub const TARGET: i64 = 399;
+
+/// Orders `solve` tries by default, well above the 120 there are.
+pub const DEFAULT_MAX_ITERATIONS: usize = 1000;
+
+/// The coins found in the ruins and the value their face shows.
+pub const COINS: [(&str, i64); 5] = [
+    ("red", 2),
+    ("corroded", 3),
+    ("shiny", 5),
+    ("concave", 7),
+    ("blue", 9),
+];
+
+fn equation(v: &[i64]) -> i64 {
+    v[0] + v[1] * v[2] * v[2] + v[3] * v[3] * v[3] - v[4]
+}
+
+fn next_permutation(order: &mut [usize]) -> bool {
+    let pivot = match (1..order.len()).rev().find(|&i| order[i - 1] < order[i]) {
+        Some(i) => i - 1,
+        None => return false,
+    };
+    let swap = (pivot + 1..order.len())
+        .rev()
+        .find(|&i| order[i] > order[pivot])
+        .unwrap();
+    order.swap(pivot, swap);
+    order[pivot + 1..].reverse();
+    true
+}
+
+/// Finds the order to place `coins` in so that `_ + _ * _^2 + _^3 - _`
+/// equals `TARGET`, returned as the commands to type. Orders are tried in
+/// lexicographic order of coin names, so when several work the one whose
+/// name sequence sorts first is returned. Gives up after trying
+/// `max_iterations` orders.
+pub fn solve(coins: &[(&str, i64)], max_iterations: usize) -> Result<Vec<String>, SolverError> {
+    if coins.len() != 5 {
+        return Err(SolverError::NoSolution);
+    }
+    let mut sorted: Vec<(&str, i64)> = coins.to_vec();
+    sorted.sort();
+
+    let mut order: Vec<usize> = (0..sorted.len()).collect();
+    for _ in 0..max_iterations {
+        let values: Vec<i64> = order.iter().map(|&i| sorted[i].1).collect();
+        if equation(&values) == TARGET {
+            return Ok(order
+                .iter()
+                .map(|&i| format!("use {} coin", sorted[i].0))
+                .collect());
+        }
+        if !next_permutation(&mut order) {
+            return Err(SolverError::NoSolution);
+        }
+    }
+    Err(SolverError::BudgetExceeded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn solves_the_ruins() {
+        assert_eq!(
+            solve(&COINS, DEFAULT_MAX_ITERATIONS).unwrap(),
+            vec![
+                "use blue coin",
+                "use red coin",
+                "use shiny coin",
+                "use concave coin",
+                "use corroded coin",
+            ]
+        );
+    }
+
+    #[test]
+    fn solution_does_not_depend_on_coin_order() {
+        let mut coins = COINS;
+        coins.reverse();
+        assert_eq!(
+            solve(&coins, DEFAULT_MAX_ITERATIONS),
+            solve(&COINS, DEFAULT_MAX_ITERATIONS)
+        );
+    }
+
+    #[test]
+    fn small_budget_is_exceeded() {
+        assert_eq!(solve(&COINS, 10), Err(SolverError::BudgetExceeded));
+    }
+}
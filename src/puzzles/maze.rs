@@ -0,0 +1,139 @@
+use super::SolverError;
+use std::collections::{HashSet, VecDeque};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Cell {
+    Num(i64),
+    Add,
+    Sub,
+    Mul,
+}
+
+/// The orb grid in front of the vault, listed from north to south. The orb
+/// starts in the south-west corner and must reach the north-east corner
+/// weighing exactly `goal`.
+pub struct Maze {
+    pub cells: Vec<Vec<Cell>>,
+    pub start: (usize, usize),
+    pub vault: (usize, usize),
+    pub goal: i64,
+}
+
+/// Orb position, weight, and the operator it picked up on its last move.
+type State = ((usize, usize), i64, Option<Cell>);
+
+/// The weight is dropped from the search as soon as it leaves this range.
+pub const MAX_WEIGHT: i64 = 1 << 15;
+
+/// States `solve` expands by default. The vault grid needs about ten
+/// thousand.
+pub const DEFAULT_MAX_ITERATIONS: usize = 1_000_000;
+
+/// Directions in the order they are tried, which is also their
+/// lexicographic order.
+const DIRECTIONS: [(&str, isize, isize); 4] = [
+    ("east", 0, 1),
+    ("north", -1, 0),
+    ("south", 1, 0),
+    ("west", 0, -1),
+];
+
+pub fn vault() -> Maze {
+    use Cell::*;
+    Maze {
+        cells: vec![
+            vec![Mul, Num(8), Sub, Num(1)],
+            vec![Num(4), Mul, Num(11), Mul],
+            vec![Add, Num(4), Sub, Num(18)],
+            vec![Num(22), Sub, Num(9), Mul],
+        ],
+        start: (3, 0),
+        vault: (0, 3),
+        goal: 30,
+    }
+}
+
+/// Breadth-first search for the moves that carry the orb to the vault. The
+/// orb may not return to the starting cell and entering the vault ends the
+/// walk. Of all shortest solutions the lexicographically smallest sequence
+/// of directions is returned: the queue is expanded in `DIRECTIONS` order,
+/// so every state is first reached along its smallest path. Gives up after
+/// expanding `max_iterations` states.
+pub fn solve(maze: &Maze, max_iterations: usize) -> Result<Vec<&'static str>, SolverError> {
+    let start_weight = match maze.cells[maze.start.0][maze.start.1] {
+        Cell::Num(n) => n,
+        _ => return Err(SolverError::NoSolution),
+    };
+    let mut expanded = 0;
+    let mut seen: HashSet<State> = HashSet::new();
+    let mut queue: VecDeque<(State, Vec<&'static str>)> = VecDeque::new();
+    queue.push_back(((maze.start, start_weight, None), Vec::new()));
+
+    while let Some(((pos, weight, pending), path)) = queue.pop_front() {
+        if expanded == max_iterations {
+            return Err(SolverError::BudgetExceeded);
+        }
+        expanded += 1;
+        for &(name, dr, dc) in DIRECTIONS.iter() {
+            let row = pos.0 as isize + dr;
+            let col = pos.1 as isize + dc;
+            if row < 0 || col < 0 {
+                continue;
+            }
+            let next = (row as usize, col as usize);
+            let cell = match maze.cells.get(next.0).and_then(|r| r.get(next.1)) {
+                Some(&cell) => cell,
+                None => continue,
+            };
+            if next == maze.start {
+                continue;
+            }
+
+            let (weight, pending) = match (cell, pending) {
+                (Cell::Num(n), Some(Cell::Add)) => (weight + n, None),
+                (Cell::Num(n), Some(Cell::Sub)) => (weight - n, None),
+                (Cell::Num(n), Some(Cell::Mul)) => (weight * n, None),
+                (Cell::Num(_), _) => continue,
+                (op, _) => (weight, Some(op)),
+            };
+            if weight <= 0 || weight >= MAX_WEIGHT {
+                continue;
+            }
+
+            let mut path = path.clone();
+            path.push(name);
+            if next == maze.vault {
+                if weight == maze.goal {
+                    return Ok(path);
+                }
+                continue;
+            }
+            if seen.insert((next, weight, pending)) {
+                queue.push_back(((next, weight, pending), path));
+            }
+        }
+    }
+
+    Err(SolverError::NoSolution)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn solves_the_vault() {
+        assert_eq!(
+            solve(&vault(), DEFAULT_MAX_ITERATIONS).unwrap(),
+            vec![
+                "north", "east", "east", "north", "west", "south", "east", "east", "west", "north",
+                "north", "east",
+            ]
+        );
+    }
+
+    #[test]
+    fn small_budget_is_exceeded() {
+        assert_eq!(solve(&vault(), 100), Err(SolverError::BudgetExceeded));
+    }
+}
@@ -0,0 +1,31 @@
+/// Letters that turn into a different letter when seen in a mirror. Every
+/// other character is taken to look the same, which holds for the
+/// characters codes are made of as far as they can be read at all.
+pub const FLIPS: [(char, char); 4] = [('b', 'd'), ('d', 'b'), ('p', 'q'), ('q', 'p')];
+
+/// What to type for a code read off a mirror: the reflection reverses the
+/// order of the characters and swaps the letters in `FLIPS`.
+pub fn mirror(code: &str) -> String {
+    code.chars()
+        .rev()
+        .map(|ch| match FLIPS.iter().find(|&&(seen, _)| seen == ch) {
+            Some(&(_, actual)) => actual,
+            None => ch,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reverses_and_flips_the_vault_code() {
+        assert_eq!(mirror("dbqWYvUTpOiH"), "HiOqTUvYWpdb");
+    }
+
+    #[test]
+    fn mirroring_twice_gives_the_code_back() {
+        assert_eq!(mirror(&mirror("pqbdXo8")), "pqbdXo8");
+    }
+}
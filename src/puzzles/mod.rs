@@ -0,0 +1,28 @@
+use std::fmt;
+
+pub mod coins;
+pub mod maze;
+pub mod mirror;
+pub mod teleporter;
+
+pub use self::mirror::mirror;
+
+/// Why a puzzle solver gave up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SolverError {
+    /// The search space was exhausted.
+    NoSolution,
+    /// The solver hit its `max_iterations` before finishing.
+    BudgetExceeded,
+}
+
+impl fmt::Display for SolverError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SolverError::NoSolution => write!(f, "no solution"),
+            SolverError::BudgetExceeded => write!(f, "iteration budget exceeded"),
+        }
+    }
+}
+
+impl std::error::Error for SolverError {}
@@ -0,0 +1,150 @@
+use super::SolverError;
+use crate::{get_op, Instruction, Machine, INTEGER_RANGE};
+use std::io;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+/// The `call` that runs the teleporter's confirmation routine. The game only
+/// gets here once r7 is nonzero.
+pub const CONFIRMATION_CALL: u16 = 5489;
+
+/// Entry of the confirmation routine, a deeply recursive function of r0, r1
+/// and r7 that takes practically forever when executed as written.
+pub const CONFIRMATION_ENTRY: u16 = 6027;
+
+pub const SLOW_CONFIRMATION_HINT: &str = "this will take a very long time; consider the \
+     teleporter patch (find the right r7 and skip the call at 5489)";
+
+/// A hint for a player who is about to wait forever: the machine has just
+/// entered the confirmation routine, which only happens while the call to
+/// it has not been patched out.
+pub fn hint(machine: &Machine) -> Option<&'static str> {
+    if machine.ip == CONFIRMATION_ENTRY {
+        Some(SLOW_CONFIRMATION_HINT)
+    } else {
+        None
+    }
+}
+
+/// How many instructions `parallel_search` spends on a single candidate.
+pub const SEARCH_STEP_LIMIT: u64 = 10_000_000;
+
+/// Candidates `parallel_search` tries by default, enough for all of them.
+pub const DEFAULT_MAX_ITERATIONS: usize = INTEGER_RANGE;
+
+/// Tries r7 candidates from 1 to 32767 on `threads` threads, each running its
+/// own copy of `template` headless. The template must be about to execute
+/// the confirmation call; a candidate wins if that call returns with r0 = 6
+/// within `SEARCH_STEP_LIMIT` instructions. The first winner stops the other
+/// threads and is reported on stderr along with the thread that found it.
+/// All threads together give up after trying `max_iterations` candidates.
+///
+/// Run against the original routine no candidate finishes in time; this is
+/// meant for a template whose routine has been replaced by something
+/// faster.
+pub fn parallel_search(
+    template: &Machine,
+    threads: usize,
+    max_iterations: usize,
+) -> Result<u16, SolverError> {
+    let threads = threads.max(1);
+    let found = AtomicBool::new(false);
+    let tried = AtomicUsize::new(0);
+    let exhausted = AtomicBool::new(false);
+    let winner = Mutex::new(None);
+    let (memory, registers, stack, ip) = (
+        &template.memory,
+        &template.registers,
+        &template.stack,
+        template.ip,
+    );
+
+    thread::scope(|scope| {
+        for id in 0..threads {
+            let (found, winner, tried, exhausted) = (&found, &winner, &tried, &exhausted);
+            scope.spawn(move || {
+                for candidate in (1..INTEGER_RANGE as u16).skip(id).step_by(threads) {
+                    if found.load(Ordering::Relaxed) {
+                        return;
+                    }
+                    if tried.fetch_add(1, Ordering::Relaxed) >= max_iterations {
+                        exhausted.store(true, Ordering::Relaxed);
+                        return;
+                    }
+                    let mut machine = Machine::new(memory.clone());
+                    machine.registers = registers.clone();
+                    machine.stack = stack.clone();
+                    machine.ip = ip;
+                    machine.output = Box::new(io::sink());
+                    machine.registers[7] = candidate;
+                    if confirms(&mut machine, found) && !found.swap(true, Ordering::Relaxed) {
+                        eprintln!("thread {} found r7 = {}", id, candidate);
+                        *winner.lock().unwrap() = Some(candidate);
+                        return;
+                    }
+                }
+            });
+        }
+    });
+
+    match winner.into_inner().unwrap() {
+        Some(candidate) => Ok(candidate),
+        None if exhausted.into_inner() => Err(SolverError::BudgetExceeded),
+        None => Err(SolverError::NoSolution),
+    }
+}
+
+fn confirms(machine: &mut Machine, cancelled: &AtomicBool) -> bool {
+    for steps in 0..SEARCH_STEP_LIMIT {
+        if steps % 65536 == 0 && cancelled.load(Ordering::Relaxed) {
+            return false;
+        }
+        if let Some(Instruction::In(_)) | None = get_op(machine) {
+            return false;
+        }
+        match machine.step() {
+            Ok(true) => {}
+            _ => return false,
+        }
+        if machine.ip == CONFIRMATION_CALL + 2 {
+            return machine.registers[0] == 6;
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble;
+
+    /// A machine about to make the confirmation call, with the routine
+    /// replaced by one that confirms only r7 = `answer`.
+    fn template(answer: u16) -> Machine {
+        let mut memory = vec![0; INTEGER_RANGE];
+        let call = assemble(&format!("call {}", CONFIRMATION_ENTRY)).unwrap();
+        memory[CONFIRMATION_CALL as usize..][..call.len()].copy_from_slice(&call);
+        let routine = assemble(&format!("eq r0 r7 {}\nmult r0 r0 6\nret", answer)).unwrap();
+        memory[CONFIRMATION_ENTRY as usize..][..routine.len()].copy_from_slice(&routine);
+        let mut machine = Machine::new(memory);
+        machine.ip = CONFIRMATION_CALL;
+        machine
+    }
+
+    #[test]
+    fn finds_the_confirming_r7() {
+        assert_eq!(
+            parallel_search(&template(25734), 4, DEFAULT_MAX_ITERATIONS),
+            Ok(25734)
+        );
+    }
+
+    #[test]
+    fn small_budget_is_exceeded() {
+        assert_eq!(
+            parallel_search(&template(25734), 2, 100),
+            Err(SolverError::BudgetExceeded)
+        );
+    }
+}
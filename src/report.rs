@@ -0,0 +1,147 @@
+use crate::analysis::{
+    disassemble, find_call_targets, find_indirect_calls, labeled_listing, labels, memory_map,
+    string_map, StringKind, MIN_STRING_LEN,
+};
+use crate::symbols::Symbols;
+use crate::{parse_number, Instruction, INTEGER_RANGE};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, Write};
+
+/// Writes a markdown document describing the program in `memory`: its
+/// memory map, subroutines, call graph, strings and the labeled disassembly.
+/// Labels are anchors, so every address with a name links to the part of
+/// the disassembly it names. Names from `symbols` replace generated ones.
+pub fn write_report<W: Write>(memory: &[u16], symbols: &Symbols, out: &mut W) -> io::Result<()> {
+    let mut names = labels(memory);
+    names.extend(symbols.iter().map(|(&addr, label)| (addr, label.clone())));
+    let link = |addr: u16| match names.get(&addr) {
+        Some(name) => format!("[{}](#{})", name, name),
+        None => addr.to_string(),
+    };
+
+    writeln!(out, "# Program report\n")?;
+    writeln!(out, "- [Memory map](#memory-map)")?;
+    writeln!(out, "- [Subroutines](#subroutines)")?;
+    writeln!(out, "- [Call graph](#call-graph)")?;
+    writeln!(out, "- [Strings](#strings)")?;
+    writeln!(out, "- [Disassembly](#disassembly)\n")?;
+
+    let map = memory_map(memory);
+    writeln!(out, "## Memory map\n")?;
+    writeln!(out, "| use | cells | share |\n| --- | ---: | ---: |")?;
+    for (name, count) in [
+        ("code", map.code),
+        ("data", map.data),
+        ("unused", map.unused),
+        ("other", map.other),
+    ] {
+        let share = count as f64 / memory.len() as f64 * 100.0;
+        writeln!(out, "| {} | {} | {:.1}% |", name, count, share)?;
+    }
+    writeln!(
+        out,
+        "\nCode is what control flow reaches statically from address 0; code \
+         only jumped to through registers, or written at run time, counts as \
+         other.\n"
+    )?;
+
+    let graph = call_graph(memory);
+    writeln!(out, "## Subroutines\n")?;
+    writeln!(
+        out,
+        "| entry | name | callers | callees |\n| ---: | --- | ---: | ---: |"
+    )?;
+    for entry in find_call_targets(memory) {
+        let callers = graph.values().filter(|callees| callees.contains(&entry));
+        writeln!(
+            out,
+            "| {} | {} | {} | {} |",
+            entry,
+            link(entry),
+            callers.count(),
+            graph.get(&Some(entry)).map_or(0, BTreeSet::len)
+        )?;
+    }
+
+    writeln!(out, "\n## Call graph\n")?;
+    writeln!(
+        out,
+        "Each call is attributed to the closest subroutine entry before it.\n"
+    )?;
+    for (caller, callees) in &graph {
+        let callees: Vec<String> = callees.iter().map(|&callee| link(callee)).collect();
+        let caller = caller.map_or_else(|| String::from("top level"), link);
+        writeln!(out, "- {} calls {}", caller, callees.join(", "))?;
+    }
+    let indirect = find_indirect_calls(memory);
+    if !indirect.is_empty() {
+        writeln!(out, "\nCalls through a register, target unknown:\n")?;
+        for addr in indirect {
+            writeln!(out, "- {}", addr)?;
+        }
+    }
+
+    writeln!(out, "\n## Strings\n")?;
+    writeln!(out, "| address | kind | text |\n| ---: | --- | --- |")?;
+    for block in string_map(memory, MIN_STRING_LEN) {
+        let kind = match block.kind {
+            StringKind::Data => "data",
+            StringKind::Out => "out",
+        };
+        writeln!(
+            out,
+            "| {} | {} | {} |",
+            link(block.start),
+            kind,
+            table_text(&block.text)
+        )?;
+    }
+
+    // Each label starts a new code block behind an anchor of its name, as
+    // links cannot point into the middle of a code block.
+    writeln!(out, "\n## Disassembly")?;
+    let mut open = false;
+    for line in labeled_listing(memory, symbols) {
+        let head = line.split(':').next().unwrap_or("").trim();
+        if parse_number(head).is_none() {
+            if open {
+                writeln!(out, "```")?;
+            }
+            writeln!(out, "\n<a id=\"{}\"></a>", head)?;
+            open = false;
+        }
+        if !open {
+            writeln!(out, "\n```")?;
+            open = true;
+        }
+        writeln!(out, "{}", line)?;
+    }
+    if open {
+        writeln!(out, "```")?;
+    }
+    out.flush()
+}
+
+/// Literal call targets by caller, the subroutine whose entry is the
+/// closest one before the `call`; `None` for calls before any entry.
+fn call_graph(memory: &[u16]) -> BTreeMap<Option<u16>, BTreeSet<u16>> {
+    let entries = find_call_targets(memory);
+    let mut graph: BTreeMap<Option<u16>, BTreeSet<u16>> = BTreeMap::new();
+    for (&addr, &instr) in &disassemble(memory) {
+        if let Instruction::Call(a) = instr {
+            if (a as usize) < INTEGER_RANGE {
+                let caller = entries.range(..=addr).next_back().copied();
+                graph.entry(caller).or_default().insert(a);
+            }
+        }
+    }
+    graph
+}
+
+/// `text` quoted for a markdown table cell.
+fn table_text(text: &str) -> String {
+    format!(
+        "`{}`",
+        format!("{:?}", text).replace('`', "'").replace('|', "\\|")
+    )
+}
@@ -0,0 +1,79 @@
+use crate::{parse_number, MEMORY_SIZE};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Write};
+
+/// Names given to addresses while reverse engineering, e.g. 5489 as
+/// `teleport_check`, shown in place of the number wherever it is printed.
+pub type Symbols = HashMap<u16, String>;
+
+/// Whether `label` can name an address: non-empty, without whitespace or
+/// `:`, and not something `parse_number` would read as a number.
+pub fn valid_label(label: &str) -> bool {
+    !label.is_empty()
+        && !label.contains(|c: char| c.is_whitespace() || c == ':')
+        && parse_number(label).is_none()
+}
+
+/// Parses one `<addr> <label>` pair per line, with `#` comments. Addresses
+/// may be decimal or 0x hex.
+pub fn parse(text: &str) -> Result<Symbols, String> {
+    let mut symbols = Symbols::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap().trim();
+        let fail = |problem: String| Err(format!("line {}: {}", number + 1, problem));
+        if line.is_empty() {
+            continue;
+        }
+        let (addr, label) = match line.split_once(char::is_whitespace) {
+            Some((addr, label)) => (addr, label.trim()),
+            None => return fail(String::from("expected `<addr> <label>`")),
+        };
+        match parse_number(addr) {
+            Some(addr) if addr >= MEMORY_SIZE => {
+                return fail(format!("address {} is outside memory", addr))
+            }
+            Some(_) if !valid_label(label) => return fail(format!("invalid label {}", label)),
+            Some(addr) => {
+                symbols.insert(addr as u16, label.to_string());
+            }
+            None => return fail(format!("invalid address {}", addr)),
+        }
+    }
+    Ok(symbols)
+}
+
+pub fn from_file(path: &str) -> io::Result<Symbols> {
+    parse(&fs::read_to_string(path)?)
+        .map_err(|problem| io::Error::new(io::ErrorKind::InvalidData, problem))
+}
+
+/// Writes `symbols` in the format `parse` reads, sorted by address.
+pub fn save<W: Write>(symbols: &Symbols, out: &mut W) -> io::Result<()> {
+    let mut sorted: Vec<_> = symbols.iter().collect();
+    sorted.sort();
+    for (addr, label) in sorted {
+        writeln!(out, "{} {}", addr, label)?;
+    }
+    out.flush()
+}
+
+/// The address `text` refers to: a number or one of the labels.
+pub fn resolve(symbols: &Symbols, text: &str) -> Option<u16> {
+    match parse_number(text) {
+        Some(addr) if addr < MEMORY_SIZE => Some(addr as u16),
+        Some(_) => None,
+        None => symbols
+            .iter()
+            .find(|(_, label)| label.as_str() == text)
+            .map(|(&addr, _)| addr),
+    }
+}
+
+/// `addr` followed by its label if it has one, e.g. `5489 (teleport_check)`.
+pub fn describe(symbols: &Symbols, addr: u16) -> String {
+    match symbols.get(&addr) {
+        Some(label) => format!("{} ({})", addr, label),
+        None => addr.to_string(),
+    }
+}
@@ -0,0 +1,300 @@
+use crate::analysis::symbolic;
+use crate::symbols::Symbols;
+use crate::{get_addr, Address, Instruction, OPCODE_NAMES};
+use std::collections::HashMap;
+use std::io::{self, BufRead, Lines, Write};
+
+/// Writes one line per executed instruction, optionally only for a chosen
+/// set of opcodes.
+pub struct Tracer {
+    out: Box<dyn Write>,
+    filter: u32,
+    annotate: bool,
+    symbols: Symbols,
+}
+
+impl Tracer {
+    pub fn new(out: Box<dyn Write>) -> Tracer {
+        Tracer {
+            out,
+            filter: !0,
+            annotate: true,
+            symbols: Symbols::new(),
+        }
+    }
+
+    /// Names to show in place of the addresses instructions refer to. A
+    /// trace written with symbols cannot serve as a `TraceComparator`
+    /// baseline.
+    pub fn set_symbols(&mut self, symbols: Symbols) {
+        self.symbols = symbols;
+    }
+
+    /// Whether lines carry register values and printed characters. Without
+    /// them a trace reads like the disassembly.
+    pub fn set_annotate(&mut self, annotate: bool) {
+        self.annotate = annotate;
+    }
+
+    /// Restricts tracing to the opcodes with the given mnemonics, e.g.
+    /// `["call", "ret"]`. Returns the first unknown name as an error.
+    pub fn set_filter<'a, I>(&mut self, names: I) -> Result<(), &'a str>
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        let mut filter = 0;
+        for name in names {
+            match OPCODE_NAMES.iter().position(|&known| known == name) {
+                Some(opcode) => filter |= 1 << opcode,
+                None => return Err(name),
+            }
+        }
+        self.filter = filter;
+        Ok(())
+    }
+
+    /// Logs `instr` about to execute at `ip`. Register operands it reads are
+    /// annotated with their current values, and `out` with the character it
+    /// prints, e.g. `add r0 r1 r2 (r1=3, r2=5)` or `out r3 ('A')`.
+    pub fn trace(&mut self, ip: u16, instr: Instruction, registers: &[u16]) {
+        if self.filter & (1 << instr.opcode()) == 0 {
+            return;
+        }
+        let text = symbolic(instr, |addr| self.symbols.get(&addr));
+        if !self.annotate {
+            let _ = writeln!(self.out, "{:5}: {}", ip, text);
+            return;
+        }
+        let resolve = |oprnd: u16| match get_addr(oprnd, registers.len()) {
+            Some(Address::Reg(reg)) => Some((reg, registers[reg])),
+            _ => None,
+        };
+        let annotation = match instr {
+            Instruction::Out(a) => {
+                let value = resolve(a).map_or(a, |(_, value)| value);
+                format!(" ({:?})", value as u8 as char)
+            }
+            _ => {
+                let reads: Vec<String> = instr
+                    .sources()
+                    .into_iter()
+                    .filter_map(resolve)
+                    .map(|(reg, value)| format!("r{}={}", reg, value))
+                    .collect();
+                if reads.is_empty() {
+                    String::new()
+                } else {
+                    format!(" ({})", reads.join(", "))
+                }
+            }
+        };
+        let _ = writeln!(self.out, "{:5}: {}{}", ip, text, annotation);
+    }
+
+    /// Logs a word at `ip` that was skipped because it does not decode.
+    pub fn skipped(&mut self, ip: u16, word: u16) {
+        let _ = writeln!(self.out, "{:5}: <invalid {}> skipped", ip, word);
+    }
+}
+
+/// Writes `ip` and all registers as a CSV row before every instruction,
+/// for plotting how registers evolve. This produces a row per executed
+/// instruction and is only practical for short stretches of execution.
+pub struct RegisterCsv {
+    out: Box<dyn Write>,
+}
+
+impl RegisterCsv {
+    /// Creates the writer and emits the header row.
+    pub fn new(mut out: Box<dyn Write>) -> RegisterCsv {
+        let _ = writeln!(out, "ip,r0,r1,r2,r3,r4,r5,r6,r7");
+        RegisterCsv { out }
+    }
+
+    pub fn record(&mut self, ip: u16, registers: &[u16]) {
+        let _ = write!(self.out, "{}", ip);
+        for value in registers {
+            let _ = write!(self.out, ",{}", value);
+        }
+        let _ = writeln!(self.out);
+    }
+}
+
+/// Checks execution against a trace recorded earlier with an unfiltered
+/// `Tracer`, reading the baseline one line at a time. Only the `ip` and the
+/// instruction are compared, not the register annotations.
+pub struct TraceComparator {
+    baseline: Lines<Box<dyn BufRead>>,
+    step: u64,
+}
+
+impl TraceComparator {
+    pub fn new(baseline: Box<dyn BufRead>) -> TraceComparator {
+        TraceComparator {
+            baseline: baseline.lines(),
+            step: 0,
+        }
+    }
+
+    /// Compares the next baseline line with `instr` about to execute at
+    /// `ip`, describing the first divergence as an error.
+    pub fn check(&mut self, ip: u16, instr: Instruction) -> Result<(), String> {
+        self.step += 1;
+        let actual = format!("{:5}: {}", ip, instr);
+        let expected = match self.baseline.next() {
+            Some(Ok(line)) => line,
+            Some(Err(err)) => return Err(format!("cannot read baseline: {}", err)),
+            None => {
+                return Err(format!(
+                    "step {}, ip {}: baseline ended, but execution continues with\n  actual:   {}",
+                    self.step, ip, actual
+                ))
+            }
+        };
+        let without_annotation = match expected.find(" (") {
+            Some(end) => &expected[..end],
+            None => &expected[..],
+        };
+        if without_annotation == actual {
+            Ok(())
+        } else {
+            Err(format!(
+                "step {}, ip {}: trace diverges\n  expected: {}\n  actual:   {}",
+                self.step, ip, without_annotation, actual
+            ))
+        }
+    }
+
+    /// Reports baseline lines left over after execution stopped.
+    pub fn finish(mut self) -> Result<(), String> {
+        match self.baseline.next() {
+            Some(Ok(line)) => Err(format!(
+                "step {}: execution stopped, but baseline continues with\n  expected: {}",
+                self.step + 1,
+                line
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Calls nested deeper than this are counted as part of the frame at this
+/// depth, which keeps the number of distinct paths bounded.
+pub const MAX_PROFILE_DEPTH: usize = 64;
+
+/// Counts executed instructions per call stack for a flame graph. Paths are
+/// kept as a tree of frames, each a subroutine entry under its caller.
+/// Direct recursion, a subroutine calling itself, is folded into a single
+/// frame so the teleporter check does not produce a path per level.
+pub struct CallProfile {
+    /// Parent and entry of every frame; frame 0 is the program itself.
+    frames: Vec<(usize, u16)>,
+    children: HashMap<(usize, u16), usize>,
+    counts: Vec<u64>,
+    /// The frame each active call runs in, innermost last.
+    active: Vec<usize>,
+}
+
+impl Default for CallProfile {
+    fn default() -> CallProfile {
+        CallProfile::new()
+    }
+}
+
+impl CallProfile {
+    pub fn new() -> CallProfile {
+        CallProfile {
+            frames: vec![(0, 0)],
+            children: HashMap::new(),
+            counts: vec![0],
+            active: Vec::new(),
+        }
+    }
+
+    fn current(&self) -> usize {
+        self.active.last().copied().unwrap_or(0)
+    }
+
+    /// Counts `instr` for the current call stack. `target` is where a
+    /// `call` goes, resolved by the caller.
+    pub fn record(&mut self, instr: Instruction, target: u16) {
+        let current = self.current();
+        self.counts[current] += 1;
+        match instr {
+            Instruction::Call(_) => {
+                let frame = if current != 0 && self.frames[current].1 == target
+                    || self.active.len() >= MAX_PROFILE_DEPTH
+                {
+                    current
+                } else {
+                    let next = self.frames.len();
+                    let frame = *self.children.entry((current, target)).or_insert(next);
+                    if frame == next {
+                        self.frames.push((current, target));
+                        self.counts.push(0);
+                    }
+                    frame
+                };
+                self.active.push(frame);
+            }
+            Instruction::Ret => {
+                self.active.pop();
+            }
+            _ => {}
+        }
+    }
+
+    /// Instructions executed per subroutine entry as `(entry, inclusive,
+    /// exclusive)`, most inclusive first. Inclusive counts include every
+    /// callee, but an instruction is only counted once per subroutine even
+    /// when it runs several levels into a recursion through it. Top-level
+    /// code outside any call is left out.
+    pub fn functions(&self) -> Vec<(u16, u64, u64)> {
+        let mut totals: HashMap<u16, (u64, u64)> = HashMap::new();
+        for (frame, &count) in self.counts.iter().enumerate().skip(1) {
+            totals.entry(self.frames[frame].1).or_default().1 += count;
+            let mut seen = Vec::new();
+            let mut node = frame;
+            while node != 0 {
+                let (parent, entry) = self.frames[node];
+                if !seen.contains(&entry) {
+                    seen.push(entry);
+                    totals.entry(entry).or_default().0 += count;
+                }
+                node = parent;
+            }
+        }
+        let mut functions: Vec<(u16, u64, u64)> = totals
+            .into_iter()
+            .map(|(entry, (inclusive, exclusive))| (entry, inclusive, exclusive))
+            .collect();
+        functions.sort_by_key(|&(entry, inclusive, _)| (std::cmp::Reverse(inclusive), entry));
+        functions
+    }
+
+    /// Writes one `main;sub_0a2b;sub_1518 1234` line per call stack that
+    /// executed anything, in the folded format flame graph tools read.
+    /// `name` gives the label for a subroutine entry.
+    pub fn write<W, F>(&self, out: &mut W, name: F) -> io::Result<()>
+    where
+        W: Write,
+        F: Fn(u16) -> String,
+    {
+        for (frame, &count) in self.counts.iter().enumerate() {
+            if count == 0 {
+                continue;
+            }
+            let mut path = Vec::new();
+            let mut node = frame;
+            while node != 0 {
+                let (parent, entry) = self.frames[node];
+                path.push(name(entry));
+                node = parent;
+            }
+            path.push(String::from("main"));
+            path.reverse();
+            writeln!(out, "{} {}", path.join(";"), count)?;
+        }
+        out.flush()
+    }
+}
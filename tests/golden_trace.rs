@@ -0,0 +1,40 @@
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::Path;
+use synacor_challenge::trace::TraceComparator;
+use synacor_challenge::{get_op, load_file, Machine};
+
+const CHALLENGE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/challenge.bin");
+const GOLDEN: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/golden/self-test.trace");
+
+/// Runs the challenge binary for as many instructions as the golden trace
+/// has lines, as `--validate-against` does, and fails on the first that
+/// differs.
+#[test]
+fn challenge_matches_golden_trace() {
+    if !Path::new(CHALLENGE).exists() {
+        println!("skipping golden trace: {} not found", CHALLENGE);
+        return;
+    }
+    let lines = fs::read_to_string(GOLDEN).unwrap().lines().count();
+    let baseline = BufReader::new(File::open(GOLDEN).unwrap());
+    let mut comparator = TraceComparator::new(Box::new(baseline));
+    let mut machine = Machine::new(load_file(CHALLENGE).unwrap());
+    machine.output = Box::new(std::io::sink());
+
+    for _ in 0..lines {
+        let instr =
+            get_op(&machine).unwrap_or_else(|| panic!("no valid instruction at ip {}", machine.ip));
+        if let Err(message) = comparator.check(machine.ip, instr) {
+            panic!("{}", message);
+        }
+        match machine.step() {
+            Ok(true) => {}
+            Ok(false) => break,
+            Err(err) => panic!("{}", err),
+        }
+    }
+    if let Err(message) = comparator.finish() {
+        panic!("{}", message);
+    }
+}
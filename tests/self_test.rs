@@ -0,0 +1,34 @@
+use std::path::Path;
+use synacor_challenge::{load_file, Machine};
+
+const CHALLENGE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/challenge.bin");
+const SELF_TEST_PASSED: &str = "self-test complete, all tests pass";
+const MAX_STEPS: u64 = 10_000_000;
+
+/// Runs the challenge binary headless up to its first `in` and checks that
+/// its built-in self-test reported success.
+#[test]
+fn challenge_self_test_passes() {
+    if !Path::new(CHALLENGE).exists() {
+        println!("skipping self-test: {} not found", CHALLENGE);
+        return;
+    }
+    let mut machine = Machine::new(load_file(CHALLENGE).unwrap());
+    machine.output = Box::new(std::io::sink());
+
+    let mut steps = 0;
+    while steps < MAX_STEPS && !machine.needs_input() {
+        match machine.step() {
+            Ok(true) => steps += 1,
+            Ok(false) => break,
+            Err(err) => panic!("self-test failed: {}", err),
+        }
+    }
+
+    let output = machine.output_since_input();
+    assert!(
+        output.contains(SELF_TEST_PASSED),
+        "self-test failed, output was:\n{}",
+        output
+    );
+}